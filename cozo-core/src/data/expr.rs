@@ -11,8 +11,10 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::mem;
 
+use crossbeam::sync::ShardedLock;
 use itertools::Itertools;
-use miette::{bail, Diagnostic, Result};
+use lazy_static::lazy_static;
+use miette::{bail, ensure, Diagnostic, Result};
 use serde::de::{Error, Visitor};
 use serde::{Deserializer, Serializer};
 use smartstring::SmartString;
@@ -20,7 +22,7 @@ use thiserror::Error;
 
 use crate::data::functions::*;
 use crate::data::symb::Symbol;
-use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::data::value::{DataValue, ValueType, LARGEST_UTF_CHAR};
 use crate::parse::expr::expr2bytecode;
 use crate::parse::SourceSpan;
 
@@ -43,6 +45,8 @@ pub enum Bytecode {
         arity: usize,
         #[serde(skip)]
         span: SourceSpan,
+        #[serde(skip)]
+        arg_spans: Box<[SourceSpan]>,
     },
     /// pop 1
     JumpIfFalse {
@@ -56,6 +60,17 @@ pub enum Bytecode {
         #[serde(skip)]
         span: SourceSpan,
     },
+    /// pop 1, and update the accumulator below it
+    Logical {
+        decisive: bool,
+        jump_to: usize,
+        #[serde(skip)]
+        span: SourceSpan,
+    },
+    /// push 1 and jump if the slot is filled, otherwise unchanged
+    Recall { slot: usize, jump_to: usize },
+    /// unchanged, fills the slot with the top of the stack
+    Memoize { slot: usize },
 }
 
 #[derive(Error, Diagnostic, Debug)]
@@ -77,7 +92,7 @@ pub fn eval_bytecode_pred(
 ) -> Result<bool> {
     match eval_bytecode(bytecodes, bindings, stack)? {
         DataValue::Bool(b) => Ok(b),
-        v => bail!(PredicateTypeError(span, v)),
+        v => bail!(PredicateTypeError::new(span, &v)),
     }
 }
 
@@ -88,6 +103,7 @@ pub fn eval_bytecode(
 ) -> Result<DataValue> {
     stack.clear();
     let mut pointer = 0;
+    let mut memo: Vec<Option<DataValue>> = vec![];
     // for (i, c) in bytecodes.iter().enumerate() {
     //     println!("{i}  {c:?}");
     // }
@@ -125,11 +141,16 @@ pub fn eval_bytecode(
                 stack.push(val.clone());
                 pointer += 1;
             }
-            Bytecode::Apply { op, arity, span } => {
+            Bytecode::Apply {
+                op,
+                arity,
+                span,
+                arg_spans,
+            } => {
                 let frame_start = stack.len() - *arity;
                 let args_frame = &stack[frame_start..];
                 let result = (op.inner)(args_frame)
-                    .map_err(|err| EvalRaisedError(*span, err.to_string()))?;
+                    .map_err(|err| raise_eval_error(err, *span, arg_spans))?;
                 stack.truncate(frame_start);
                 stack.push(result);
                 pointer += 1;
@@ -138,7 +159,7 @@ pub fn eval_bytecode(
                 let val = stack.pop().unwrap();
                 let cond = val
                     .get_bool()
-                    .ok_or_else(|| PredicateTypeError(*span, val))?;
+                    .ok_or_else(|| PredicateTypeError::new(*span, &val))?;
                 if cond {
                     pointer += 1;
                 } else {
@@ -148,6 +169,42 @@ pub fn eval_bytecode(
             Bytecode::Goto { jump_to, .. } => {
                 pointer = *jump_to;
             }
+            Bytecode::Logical {
+                decisive,
+                jump_to,
+                span,
+            } => {
+                let val = stack.pop().unwrap();
+                match val {
+                    DataValue::Bool(b) if b == *decisive => {
+                        *stack.last_mut().unwrap() = val;
+                        pointer = *jump_to;
+                    }
+                    DataValue::Bool(_) => {
+                        pointer += 1;
+                    }
+                    DataValue::Null => {
+                        *stack.last_mut().unwrap() = DataValue::Null;
+                        pointer += 1;
+                    }
+                    val => bail!(PredicateTypeError::new(*span, &val)),
+                }
+            }
+            Bytecode::Recall { slot, jump_to } => {
+                if let Some(Some(val)) = memo.get(*slot) {
+                    stack.push(val.clone());
+                    pointer = *jump_to;
+                } else {
+                    pointer += 1;
+                }
+            }
+            Bytecode::Memoize { slot } => {
+                if memo.len() <= *slot {
+                    memo.resize(*slot + 1, None);
+                }
+                memo[*slot] = stack.last().cloned();
+                pointer += 1;
+            }
         }
     }
     Ok(stack.pop().unwrap())
@@ -175,7 +232,7 @@ pub enum Expr {
     Apply {
         /// Op representing the function to apply
         op: &'static Op,
-        /// Arguments to the application
+        /// Arguments to the application, always evaluated from left to right
         args: Box<[Expr]>,
         /// Source span
         #[serde(skip)]
@@ -227,9 +284,31 @@ impl Display for Expr {
 }
 
 #[derive(Debug, Error, Diagnostic)]
-#[error("Found value {1:?} where a boolean value is expected")]
+#[error("Found value {1} where a boolean value is expected")]
 #[diagnostic(code(eval::predicate_not_bool))]
-pub(crate) struct PredicateTypeError(#[label] pub(crate) SourceSpan, pub(crate) DataValue);
+pub(crate) struct PredicateTypeError(#[label] pub(crate) SourceSpan, String);
+
+impl PredicateTypeError {
+    pub(crate) fn new(span: SourceSpan, val: &DataValue) -> Self {
+        Self(span, summarize_value(val))
+    }
+}
+
+/// Short description of a value for error messages: collections and long strings
+/// are not printed in full, since they may be arbitrarily large.
+fn summarize_value(val: &DataValue) -> String {
+    const MAX_CHARS: usize = 32;
+    match val {
+        DataValue::List(l) => format!("<list of length {}>", l.len()),
+        DataValue::Set(s) => format!("<set of length {}>", s.len()),
+        DataValue::Bytes(b) => format!("<bytes of length {}>", b.len()),
+        DataValue::Str(s) if s.chars().count() > MAX_CHARS => {
+            let prefix: String = s.chars().take(MAX_CHARS).collect();
+            format!("{prefix:?}...")
+        }
+        v => format!("{v:?}"),
+    }
+}
 
 #[derive(Debug, Error, Diagnostic)]
 #[error("Cannot build entity ID from {0:?}")]
@@ -242,6 +321,20 @@ struct BadEntityId(DataValue, #[label] SourceSpan);
 #[diagnostic(code(eval::throw))]
 struct EvalRaisedError(#[label] SourceSpan, #[help] String);
 
+/// Wraps an error raised by an op, pointing at the offending argument if the op said which.
+fn raise_eval_error(
+    err: miette::Report,
+    span: SourceSpan,
+    arg_spans: &[SourceSpan],
+) -> EvalRaisedError {
+    let span = err
+        .downcast_ref::<ArgTypeError>()
+        .and_then(|e| arg_spans.get(e.position))
+        .copied()
+        .unwrap_or(span);
+    EvalRaisedError(span, err.to_string())
+}
+
 impl Expr {
     pub(crate) fn compile(&self) -> Vec<Bytecode> {
         let mut collector = vec![];
@@ -368,20 +461,25 @@ impl Expr {
         self.partial_eval()?;
         match self {
             Expr::Const { val, .. } => Ok(val),
+            // folding failed: evaluate again to report the actual error
+            e if e.bindings().is_empty() => e.eval(vec![]),
             _ => bail!(NotConstError),
         }
     }
     pub(crate) fn partial_eval(&mut self) -> Result<()> {
-        if let Expr::Apply { args, span, .. } = self {
+        if let Expr::Apply { op, args, span } = self {
             let span = *span;
-            let mut all_evaluated = true;
+            let mut all_evaluated = !op.has_side_effect();
             for arg in args.iter_mut() {
                 arg.partial_eval()?;
                 all_evaluated = all_evaluated && matches!(arg, Expr::Const { .. });
             }
+            // errors are left for runtime, since a short-circuiting `and`/`or`
+            // may never evaluate this subexpression
             if all_evaluated {
-                let result = self.eval(&vec![])?;
-                mem::swap(self, &mut Expr::Const { val: result, span });
+                if let Ok(result) = self.eval(vec![]) {
+                    mem::swap(self, &mut Expr::Const { val: result, span });
+                }
             }
             // nested not's can accumulate during conversion to normal form
             if let Expr::Apply {
@@ -407,72 +505,235 @@ impl Expr {
         }
         Ok(())
     }
-    pub(crate) fn bindings(&self) -> BTreeSet<Symbol> {
-        let mut ret = BTreeSet::new();
-        self.collect_bindings(&mut ret);
-        ret
+    /// Whether evaluating may have effects, or give different results each time
+    pub(crate) fn has_side_effect(&self) -> bool {
+        let mut found = false;
+        self.walk(&mut |e| {
+            if let Expr::Apply { op, .. } = e {
+                found |= op.has_side_effect();
+            }
+        });
+        found
     }
-    pub(crate) fn collect_bindings(&self, coll: &mut BTreeSet<Symbol>) {
+    /// Visits the expression tree in pre-order: every node comes before its children,
+    /// and children are visited from left to right
+    pub(crate) fn walk<F: FnMut(&Expr)>(&self, f: &mut F) {
+        f(self);
         match self {
-            Expr::Binding { var, .. } => {
-                coll.insert(var.clone());
-            }
-            Expr::Const { .. } => {}
+            Expr::Binding { .. } | Expr::Const { .. } => {}
             Expr::Apply { args, .. } => {
                 for arg in args.iter() {
-                    arg.collect_bindings(coll)
+                    arg.walk(f)
                 }
             }
             Expr::Cond { clauses, .. } => {
                 for (cond, val) in clauses {
-                    cond.collect_bindings(coll);
-                    val.collect_bindings(coll)
+                    cond.walk(f);
+                    val.walk(f)
                 }
             }
         }
     }
-    pub(crate) fn eval(&self, bindings: impl AsRef<[DataValue]>) -> Result<DataValue> {
+    pub(crate) fn bindings(&self) -> BTreeSet<Symbol> {
+        let mut ret = BTreeSet::new();
+        self.collect_bindings(&mut ret);
+        ret
+    }
+    pub(crate) fn collect_bindings(&self, coll: &mut BTreeSet<Symbol>) {
+        self.walk(&mut |e| {
+            if let Expr::Binding { var, .. } = e {
+                coll.insert(var.clone());
+            }
+        })
+    }
+    /// Evaluates the expression outside of a query, looking up variables by name,
+    /// as in keys made with `Symbol::from("x")`.
+    /// It is an error for a variable of the expression to be missing from `bindings`.
+    pub fn evaluate(&self, bindings: &BTreeMap<Symbol, DataValue>) -> Result<DataValue> {
+        let mut binding_map = BTreeMap::new();
+        let mut tuple = vec![];
+        for var in self.bindings() {
+            let val = bindings
+                .get(&var)
+                .ok_or_else(|| UnboundVariableError(var.name.to_string(), var.span))?;
+            binding_map.insert(var, tuple.len());
+            tuple.push(val.clone());
+        }
+        let mut expr = self.clone();
+        expr.fill_binding_indices(&binding_map)?;
+        expr.eval(tuple)
+    }
+    /// The type of the value the expression produces whenever its evaluation succeeds,
+    /// or `None` if that depends on the values bound at runtime.
+    pub fn infer_type(&self) -> Option<ValueType> {
         match self {
-            Expr::Binding { var, tuple_pos, .. } => match tuple_pos {
-                None => {
-                    bail!(UnboundVariableError(var.name.to_string(), var.span))
-                }
-                Some(i) => Ok(bindings
-                    .as_ref()
-                    .get(*i)
-                    .ok_or_else(|| {
-                        TupleTooShortError(
-                            var.name.to_string(),
-                            *i,
-                            bindings.as_ref().len(),
-                            var.span,
-                        )
-                    })?
-                    .clone()),
-            },
-            Expr::Const { val, .. } => Ok(val.clone()),
+            Expr::Binding { .. } => None,
+            Expr::Const { val, .. } => ValueType::of(val),
             Expr::Apply { op, args, .. } => {
-                let args: Box<[DataValue]> = args
-                    .iter()
-                    .map(|v| v.eval(bindings.as_ref()))
-                    .try_collect()?;
-                Ok((op.inner)(&args)
-                    .map_err(|err| EvalRaisedError(self.span(), err.to_string()))?)
+                let arg_types = args.iter().map(|a| a.infer_type()).collect_vec();
+                op.result_type(&arg_types)
             }
             Expr::Cond { clauses, .. } => {
-                for (cond, val) in clauses {
-                    let cond_val = cond.eval(bindings.as_ref())?;
+                // without a catch-all clause, falling through produces null
+                let exhaustive = matches!(
+                    clauses.last(),
+                    Some((
+                        Expr::Const {
+                            val: DataValue::Bool(true),
+                            ..
+                        },
+                        _
+                    ))
+                );
+                if !exhaustive {
+                    return None;
+                }
+                let mut types = clauses.iter().map(|(_, val)| val.infer_type());
+                let first = types.next()??;
+                types.all(|t| t == Some(first)).then_some(first)
+            }
+        }
+    }
+    /// Evaluates with an explicit work stack instead of recursion,
+    /// so that deeply nested expressions cannot overflow the call stack
+    pub(crate) fn eval(&self, bindings: impl AsRef<[DataValue]>) -> Result<DataValue> {
+        enum Step<'a> {
+            Eval(&'a Expr),
+            /// All arguments of the application are on top of the value stack
+            Apply {
+                op: &'static Op,
+                args: &'a [Expr],
+                span: SourceSpan,
+            },
+            /// Argument `idx` of an `and`/`or` is on top of the value stack
+            Logic {
+                args: &'a [Expr],
+                decisive: bool,
+                idx: usize,
+                ret: DataValue,
+            },
+            /// The condition of clause `idx` is on top of the value stack
+            Branch {
+                clauses: &'a [(Expr, Expr)],
+                idx: usize,
+            },
+        }
+
+        let bindings = bindings.as_ref();
+        let mut steps = vec![Step::Eval(self)];
+        let mut stack: Vec<DataValue> = vec![];
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Eval(expr) => match expr {
+                    Expr::Binding { var, tuple_pos, .. } => match tuple_pos {
+                        None => {
+                            bail!(UnboundVariableError(var.name.to_string(), var.span))
+                        }
+                        Some(i) => stack.push(
+                            bindings
+                                .get(*i)
+                                .ok_or_else(|| {
+                                    TupleTooShortError(
+                                        var.name.to_string(),
+                                        *i,
+                                        bindings.len(),
+                                        var.span,
+                                    )
+                                })?
+                                .clone(),
+                        ),
+                    },
+                    Expr::Const { val, .. } => stack.push(val.clone()),
+                    Expr::Apply { op, args, .. } if **op == OP_AND || **op == OP_OR => {
+                        let decisive = **op == OP_OR;
+                        let ret = DataValue::from(!decisive);
+                        if args.is_empty() {
+                            stack.push(ret);
+                        } else {
+                            steps.push(Step::Logic {
+                                args,
+                                decisive,
+                                idx: 0,
+                                ret,
+                            });
+                            steps.push(Step::Eval(&args[0]));
+                        }
+                    }
+                    Expr::Apply { op, args, span } => {
+                        steps.push(Step::Apply {
+                            op,
+                            args,
+                            span: *span,
+                        });
+                        // left to right, matching the order of the bytecode
+                        steps.extend(args.iter().rev().map(Step::Eval));
+                    }
+                    Expr::Cond { clauses, .. } => match clauses.first() {
+                        None => stack.push(DataValue::Null),
+                        Some((cond, _)) => {
+                            steps.push(Step::Branch { clauses, idx: 0 });
+                            steps.push(Step::Eval(cond));
+                        }
+                    },
+                },
+                Step::Apply { op, args, span } => {
+                    let vals = stack.split_off(stack.len() - args.len());
+                    let val = (op.inner)(&vals).map_err(|err| {
+                        let arg_spans = args.iter().map(|a| a.span()).collect_vec();
+                        raise_eval_error(err, span, &arg_spans)
+                    })?;
+                    stack.push(val);
+                }
+                Step::Logic {
+                    args,
+                    decisive,
+                    idx,
+                    mut ret,
+                } => {
+                    // the first operand deciding the result stops the evaluation
+                    match stack.pop().unwrap() {
+                        DataValue::Bool(b) if b == decisive => {
+                            stack.push(DataValue::from(b));
+                            continue;
+                        }
+                        DataValue::Bool(_) => {}
+                        DataValue::Null => ret = DataValue::Null,
+                        val => bail!(PredicateTypeError::new(args[idx].span(), &val)),
+                    }
+                    match args.get(idx + 1) {
+                        Some(next) => {
+                            steps.push(Step::Logic {
+                                args,
+                                decisive,
+                                idx: idx + 1,
+                                ret,
+                            });
+                            steps.push(Step::Eval(next));
+                        }
+                        None => stack.push(ret),
+                    }
+                }
+                Step::Branch { clauses, idx } => {
+                    let (cond, val) = &clauses[idx];
+                    let cond_val = stack.pop().unwrap();
                     let cond_val = cond_val
                         .get_bool()
-                        .ok_or_else(|| PredicateTypeError(cond.span(), cond_val))?;
-
+                        .ok_or_else(|| PredicateTypeError::new(cond.span(), &cond_val))?;
                     if cond_val {
-                        return val.eval(bindings.as_ref());
+                        steps.push(Step::Eval(val));
+                    } else if let Some((next_cond, _)) = clauses.get(idx + 1) {
+                        steps.push(Step::Branch {
+                            clauses,
+                            idx: idx + 1,
+                        });
+                        steps.push(Step::Eval(next_cond));
+                    } else {
+                        stack.push(DataValue::Null);
                     }
                 }
-                Ok(DataValue::Null)
             }
         }
+        Ok(stack.pop().unwrap())
     }
     pub(crate) fn extract_bound(&self, target: &Symbol) -> Result<ValueRange> {
         Ok(match self {
@@ -633,9 +894,34 @@ pub struct Op {
     pub(crate) name: &'static str,
     pub(crate) min_arity: usize,
     pub(crate) vararg: bool,
+    pub(crate) side_effect: bool,
+    pub(crate) deterministic: bool,
+    pub(crate) commutative: bool,
+    pub(crate) associative: bool,
+    pub(crate) identity: Option<DataValue>,
+    pub(crate) returns: ReturnType,
     pub(crate) inner: fn(&[DataValue]) -> Result<DataValue>,
 }
 
+/// How the type of a successful result of an op follows from the types of its arguments
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ReturnType {
+    /// Not known before the op is applied
+    Unknown,
+    /// The same type whatever the arguments
+    Always(ValueType),
+    /// Ints if every operand is an int, otherwise floats if any operand is, as in arithmetic
+    Promoted,
+    /// The type of the operands if they are all ints or all floats, as one of them is returned
+    Uniform,
+    /// Booleans if every operand is a boolean, as null operands make the result null
+    Logical,
+    /// A string or a list, whichever the first argument is
+    SameAsFirst,
+    /// The type of the first argument, if it is known not to be null
+    Coalesced,
+}
+
 impl serde::Serialize for &'_ Op {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
@@ -687,23 +973,42 @@ impl Debug for Op {
 }
 
 pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
+    get_op_builtin(name).or_else(|| CUSTOM_OPS.read().unwrap().get(name).copied())
+}
+
+fn get_op_builtin(name: &str) -> Option<&'static Op> {
     Some(match name {
         "coalesce" => &OP_COALESCE,
+        "nullif" => &OP_NULLIF,
         "list" => &OP_LIST,
         "add" => &OP_ADD,
         "sub" => &OP_SUB,
         "mul" => &OP_MUL,
         "div" => &OP_DIV,
+        "int_div" => &OP_INT_DIV,
+        "try_div" => &OP_TRY_DIV,
         "minus" => &OP_MINUS,
+        "neg" => &OP_MINUS,
         "abs" => &OP_ABS,
         "signum" => &OP_SIGNUM,
         "floor" => &OP_FLOOR,
         "ceil" => &OP_CEIL,
         "round" => &OP_ROUND,
         "mod" => &OP_MOD,
+        "gcd" => &OP_GCD,
+        "lcm" => &OP_LCM,
         "max" => &OP_MAX,
         "min" => &OP_MIN,
+        "max_ignore_nan" => &OP_MAX_IGNORE_NAN,
+        "min_ignore_nan" => &OP_MIN_IGNORE_NAN,
+        "greatest" => &OP_GREATEST,
+        "least" => &OP_LEAST,
+        "sum" => &OP_SUM,
+        "product" => &OP_PRODUCT,
+        "avg" => &OP_AVG,
         "pow" => &OP_POW,
+        "lerp" => &OP_LERP,
+        "clamp01" => &OP_CLAMP01,
         "exp" => &OP_EXP,
         "exp2" => &OP_EXP2,
         "ln" => &OP_LN,
@@ -728,25 +1033,42 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "ge" => &OP_GE,
         "lt" => &OP_LT,
         "le" => &OP_LE,
+        "cmp" => &OP_CMP,
+        "between" => &OP_BETWEEN,
         "or" => &OP_OR,
         "and" => &OP_AND,
+        "xor" => &OP_XOR,
         "negate" => &OP_NEGATE,
+        "not" => &OP_NEGATE,
         "bit_and" => &OP_BIT_AND,
         "bit_or" => &OP_BIT_OR,
         "bit_not" => &OP_BIT_NOT,
         "bit_xor" => &OP_BIT_XOR,
+        "bit_count" => &OP_BIT_COUNT,
+        "popcount" => &OP_BIT_COUNT,
+        "leading_zeros" => &OP_LEADING_ZEROS,
+        "trailing_zeros" => &OP_TRAILING_ZEROS,
         "pack_bits" => &OP_PACK_BITS,
         "unpack_bits" => &OP_UNPACK_BITS,
         "concat" => &OP_CONCAT,
         "str_includes" => &OP_STR_INCLUDES,
+        "index_of" => &OP_INDEX_OF,
+        "string_count" => &OP_STRING_COUNT,
+        "levenshtein" => &OP_LEVENSHTEIN,
         "lowercase" => &OP_LOWERCASE,
         "uppercase" => &OP_UPPERCASE,
+        "capitalize" => &OP_CAPITALIZE,
+        "title_case" => &OP_TITLE_CASE,
         "trim" => &OP_TRIM,
         "trim_start" => &OP_TRIM_START,
         "trim_end" => &OP_TRIM_END,
+        "string_repeat" => &OP_STRING_REPEAT,
+        "pad_left" => &OP_PAD_LEFT,
+        "pad_right" => &OP_PAD_RIGHT,
         "starts_with" => &OP_STARTS_WITH,
         "ends_with" => &OP_ENDS_WITH,
         "is_null" => &OP_IS_NULL,
+        "is_not_null" => &OP_IS_NOT_NULL,
         "is_int" => &OP_IS_INT,
         "is_float" => &OP_IS_FLOAT,
         "is_num" => &OP_IS_NUM,
@@ -754,13 +1076,24 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "is_list" => &OP_IS_LIST,
         "is_bytes" => &OP_IS_BYTES,
         "is_in" => &OP_IS_IN,
+        "in" => &OP_IN,
         "is_finite" => &OP_IS_FINITE,
         "is_infinite" => &OP_IS_INFINITE,
         "is_nan" => &OP_IS_NAN,
         "is_uuid" => &OP_IS_UUID,
+        "typeof" => &OP_TYPEOF,
+        "hash" => &OP_HASH,
         "length" => &OP_LENGTH,
+        "len" => &OP_LENGTH,
+        "has" => &OP_HAS,
         "sorted" => &OP_SORTED,
+        "sort" => &OP_SORTED,
+        "sort_desc" => &OP_SORT_DESC,
         "reverse" => &OP_REVERSE,
+        "distinct" => &OP_DISTINCT,
+        "dedup" => &OP_DISTINCT,
+        "list_map" => &OP_LIST_MAP,
+        "range" => &OP_RANGE,
         "append" => &OP_APPEND,
         "prepend" => &OP_PREPEND,
         "unicode_normalize" => &OP_UNICODE_NORMALIZE,
@@ -771,24 +1104,42 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "get" => &OP_GET,
         "maybe_get" => &OP_MAYBE_GET,
         "chars" => &OP_CHARS,
+        "to_chars" => &OP_CHARS,
         "from_substrings" => &OP_FROM_SUBSTRINGS,
+        "from_chars" => &OP_FROM_CHARS,
         "slice" => &OP_SLICE,
+        "sublist" => &OP_SUBLIST,
         "regex_matches" => &OP_REGEX_MATCHES,
         "regex_replace" => &OP_REGEX_REPLACE,
         "regex_replace_all" => &OP_REGEX_REPLACE_ALL,
+        "string_replace_regex" => &OP_REGEX_REPLACE_ALL,
         "regex_extract" => &OP_REGEX_EXTRACT,
         "regex_extract_first" => &OP_REGEX_EXTRACT_FIRST,
         "encode_base64" => &OP_ENCODE_BASE64,
         "decode_base64" => &OP_DECODE_BASE64,
+        "to_base64" => &OP_ENCODE_BASE64,
+        "from_base64" => &OP_DECODE_BASE64,
+        "to_hex" => &OP_TO_HEX,
+        "from_hex" => &OP_FROM_HEX,
         "first" => &OP_FIRST,
         "last" => &OP_LAST,
+        "rest" => &OP_REST,
+        "take" => &OP_TAKE,
+        "drop" => &OP_DROP,
         "chunks" => &OP_CHUNKS,
+        "chunk" => &OP_CHUNKS,
         "chunks_exact" => &OP_CHUNKS_EXACT,
         "windows" => &OP_WINDOWS,
+        "zip" => &OP_ZIP,
+        "flatten" => &OP_FLATTEN,
         "to_int" => &OP_TO_INT,
+        "parse_int_radix" => &OP_PARSE_INT_RADIX,
         "to_float" => &OP_TO_FLOAT,
         "to_string" => &OP_TO_STRING,
+        "format" => &OP_FORMAT,
+        "format_float" => &OP_FORMAT_FLOAT,
         "rand_float" => &OP_RAND_FLOAT,
+        "rand" => &OP_RAND_FLOAT,
         "rand_bernoulli" => &OP_RAND_BERNOULLI,
         "rand_int" => &OP_RAND_INT,
         "rand_choose" => &OP_RAND_CHOOSE,
@@ -796,11 +1147,18 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "union" => &OP_UNION,
         "intersection" => &OP_INTERSECTION,
         "difference" => &OP_DIFFERENCE,
+        "map" => &OP_MAP,
+        "map_get" => &OP_MAP_GET,
+        "map_keys" => &OP_MAP_KEYS,
+        "map_values" => &OP_MAP_VALUES,
+        "map_put" => &OP_MAP_PUT,
         "to_uuid" => &OP_TO_UUID,
         "to_bool" => &OP_TO_BOOL,
         "to_unity" => &OP_TO_UNITY,
         "rand_uuid_v1" => &OP_RAND_UUID_V1,
         "rand_uuid_v4" => &OP_RAND_UUID_V4,
+        "uuid" => &OP_RAND_UUID_V4,
+        "parse_uuid" => &OP_TO_UUID,
         "uuid_timestamp" => &OP_UUID_TIMESTAMP,
         "now" => &OP_NOW,
         "format_timestamp" => &OP_FORMAT_TIMESTAMP,
@@ -809,7 +1167,129 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
     })
 }
 
+lazy_static! {
+    static ref CUSTOM_OPS: ShardedLock<BTreeMap<String, &'static Op>> = Default::default();
+}
+
+/// Register a function implemented in Rust, so that scripts can call it as `name(...)`.
+/// It takes at least `min_arity` arguments, and more only if `vararg` is set.
+/// Set `side_effect` if a call must never be skipped or evaluated ahead of time,
+/// and clear `deterministic` if equal arguments may give different results,
+/// otherwise calls may be folded into constants and repeated calls share one result.
+/// Registered functions are shared by every database in the process and stay registered
+/// until it exits: the memory of each is never freed, so register at startup only.
+/// The name must be in lowercase ASCII and not already be in use.
+pub fn register_op(
+    name: &str,
+    min_arity: usize,
+    vararg: bool,
+    side_effect: bool,
+    deterministic: bool,
+    inner: fn(&[DataValue]) -> Result<DataValue>,
+) -> Result<()> {
+    let valid_name = name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    ensure!(
+        valid_name && !matches!(name, "cond" | "if"),
+        "{name:?} cannot be used as the name of a function"
+    );
+    let op_name = format!("OP_{}", name.to_ascii_uppercase());
+    // ops are told apart by their names, and those only used internally have no name in scripts
+    let internal = [&OP_REGEX].iter().any(|op| op.name == op_name);
+    let mut custom_ops = CUSTOM_OPS.write().unwrap();
+    if get_op_builtin(name).is_some() || internal || custom_ops.contains_key(name) {
+        bail!("A function with the name {name} is already registered")
+    }
+    // nothing is assumed about the algebra or the results of functions from outside
+    let op = Op {
+        name: Box::leak(op_name.into_boxed_str()),
+        min_arity,
+        vararg,
+        side_effect,
+        deterministic,
+        commutative: false,
+        associative: false,
+        identity: None,
+        returns: ReturnType::Unknown,
+        inner,
+    };
+    custom_ops.insert(name.to_string(), Box::leak(Box::new(op)));
+    Ok(())
+}
+
 impl Op {
+    /// The properties of ops that do not say otherwise: pure, with no algebraic properties
+    /// known, and results of unknown type. Only used to fill in the fields left out when
+    /// defining an op.
+    pub(crate) const PLAIN: Op = Op {
+        name: "",
+        min_arity: 0,
+        vararg: false,
+        side_effect: false,
+        deterministic: true,
+        commutative: false,
+        associative: false,
+        identity: None,
+        returns: ReturnType::Unknown,
+        inner: |_| unreachable!(),
+    };
+    /// Non-deterministic ops must not be folded into constants ahead of time
+    pub(crate) fn has_side_effect(&self) -> bool {
+        self.side_effect
+    }
+    /// Whether the same arguments always give the same result, so that a result can be reused
+    /// for another application with equal arguments. Unlike [`Op::has_side_effect`] this says
+    /// nothing about whether the application may be skipped or reordered.
+    pub(crate) fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+    /// Whether the order of the arguments never matters to the result
+    pub(crate) fn is_commutative(&self) -> bool {
+        self.commutative
+    }
+    /// Whether nested applications can be regrouped, `f(f(a, b), c) == f(a, f(b, c))`.
+    /// For floats `+` and `*` only hold this up to rounding.
+    pub(crate) fn is_associative(&self) -> bool {
+        self.associative
+    }
+    /// The value `e` with `f(e, x) == x` for every valid `x`, if there is one
+    pub(crate) fn identity_element(&self) -> Option<DataValue> {
+        self.identity.clone()
+    }
+    /// The type of a successful result given the statically known types of the arguments,
+    /// as used by [`Expr::infer_type`]
+    pub(crate) fn result_type(&self, arg_types: &[Option<ValueType>]) -> Option<ValueType> {
+        let all_of = |t: ValueType| arg_types.iter().all(|a| *a == Some(t));
+        Some(match self.returns {
+            ReturnType::Unknown => return None,
+            ReturnType::Always(t) => t,
+            ReturnType::Promoted => {
+                if all_of(ValueType::Int) {
+                    ValueType::Int
+                } else if arg_types.contains(&Some(ValueType::Float)) {
+                    ValueType::Float
+                } else {
+                    ValueType::Number
+                }
+            }
+            ReturnType::Uniform => match arg_types.first() {
+                Some(Some(t @ (ValueType::Int | ValueType::Float))) if all_of(*t) => *t,
+                _ => ValueType::Number,
+            },
+            ReturnType::Logical if all_of(ValueType::Bool) => ValueType::Bool,
+            ReturnType::Logical => return None,
+            ReturnType::SameAsFirst => match arg_types.first() {
+                Some(Some(t @ (ValueType::String | ValueType::List))) => *t,
+                _ => return None,
+            },
+            ReturnType::Coalesced => match arg_types.first() {
+                Some(Some(t)) if *t != ValueType::Null => *t,
+                _ => return None,
+            },
+        })
+    }
     pub(crate) fn post_process_args(&self, args: &mut [Expr]) {
         if self.name.starts_with("OP_REGEX_") {
             args[1] = Expr::Apply {
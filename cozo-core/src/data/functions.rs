@@ -6,8 +6,8 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::cmp::Reverse;
-use std::collections::BTreeSet;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet};
 use std::ops::{Div, Rem};
 use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -18,24 +18,63 @@ use chrono::{DateTime, TimeZone, Utc};
 use itertools::Itertools;
 #[cfg(target_arch = "wasm32")]
 use js_sys::Date;
-use miette::{bail, ensure, miette, Result};
+use miette::{bail, ensure, miette, Diagnostic, Result};
 use num_traits::FloatConst;
 use rand::prelude::*;
 use smartstring::SmartString;
+use thiserror::Error;
 use unicode_normalization::UnicodeNormalization;
 use uuid::v1::Timestamp;
 
-use crate::data::expr::Op;
+use crate::data::expr::{get_op, Op, ReturnType};
 use crate::data::json::JsonValue;
-use crate::data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs};
+use crate::data::memcmp::MemCmpEncoder;
+use crate::data::value::{
+    DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs, ValueType,
+};
+
+/// Raised by an op when one of its arguments has the wrong type. The evaluator
+/// uses `position` to point the diagnostic at the offending argument.
+#[derive(Debug, Error, Diagnostic)]
+#[error("'{op}' requires {expected} for argument {}", .position + 1)]
+#[diagnostic(code(eval::bad_arg_type))]
+pub(crate) struct ArgTypeError {
+    op: &'static str,
+    pub(crate) position: usize,
+    expected: &'static str,
+}
+
+impl ArgTypeError {
+    pub(crate) fn new(op: &'static str, position: usize, expected: &'static str) -> Self {
+        Self {
+            op,
+            position,
+            expected,
+        }
+    }
+}
 
 macro_rules! define_op {
-    ($name:ident, $min_arity:expr, $vararg:expr) => {
+    // effectful ops, which also give a different result each time
+    ($name:ident, $min_arity:expr, $vararg:expr, impure $(, $prop:ident: $value:expr)* $(,)?) => {
+        define_op!(
+            $name,
+            $min_arity,
+            $vararg,
+            side_effect: true,
+            deterministic: false
+            $(, $prop: $value)*
+        );
+    };
+    // properties that are not given are those of `Op::PLAIN`
+    ($name:ident, $min_arity:expr, $vararg:expr $(, $prop:ident: $value:expr)* $(,)?) => {
         pub(crate) const $name: Op = Op {
             name: stringify!($name),
             min_arity: $min_arity,
             vararg: $vararg,
             inner: ::casey::lower!($name),
+            $($prop: $value,)*
+            ..Op::PLAIN
         };
     };
 }
@@ -49,9 +88,11 @@ fn ensure_same_value_type(a: &DataValue, b: &DataValue) -> Result<()> {
             | (Num(_), Num(_))
             | (Str(_), Str(_))
             | (Bytes(_), Bytes(_))
+            | (Uuid(_), Uuid(_))
             | (Regex(_), Regex(_))
             | (List(_), List(_))
             | (Set(_), Set(_))
+            | (Map(_), Map(_))
             | (Bot, Bot)
     ) {
         bail!(
@@ -63,12 +104,13 @@ fn ensure_same_value_type(a: &DataValue, b: &DataValue) -> Result<()> {
     Ok(())
 }
 
-define_op!(OP_LIST, 0, true);
+define_op!(OP_LIST, 0, true, returns: ReturnType::Always(ValueType::List));
 pub(crate) fn op_list(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List(args.to_vec()))
 }
 
-define_op!(OP_COALESCE, 0, true);
+define_op!(OP_COALESCE, 1, true, returns: ReturnType::Coalesced);
+// all arguments are evaluated before the call, so there is no short-circuiting
 pub(crate) fn op_coalesce(args: &[DataValue]) -> Result<DataValue> {
     for val in args {
         if *val != DataValue::Null {
@@ -78,21 +120,50 @@ pub(crate) fn op_coalesce(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::Null)
 }
 
-define_op!(OP_EQ, 2, false);
-pub(crate) fn op_eq(args: &[DataValue]) -> Result<DataValue> {
-    Ok(DataValue::from(match (&args[0], &args[1]) {
-        (DataValue::Num(Num::Float(f)), DataValue::Num(Num::Int(i)))
-        | (DataValue::Num(Num::Int(i)), DataValue::Num(Num::Float(f))) => *i as f64 == *f,
+// numbers are compared by value whether they are ints or floats, and NaN equals nothing,
+// also when nested in lists
+fn values_eq(a: &DataValue, b: &DataValue) -> bool {
+    match (a, b) {
+        (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Int(r))) => l == r,
+        (DataValue::Num(l), DataValue::Num(r)) => l.get_float() == r.get_float(),
+        (DataValue::List(l), DataValue::List(r)) => {
+            l.len() == r.len() && l.iter().zip(r.iter()).all(|(l, r)| values_eq(l, r))
+        }
         (a, b) => a == b,
-    }))
+    }
 }
 
-define_op!(OP_IS_UUID, 1, false);
+// `None` if the values are unordered, which happens only when NaN is involved
+fn compare_values(a: &DataValue, b: &DataValue) -> Result<Option<Ordering>> {
+    ensure_same_value_type(a, b)?;
+    Ok(match (a, b) {
+        (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Int(r))) => Some(l.cmp(r)),
+        (DataValue::Num(l), DataValue::Num(r)) => l.get_float().partial_cmp(&r.get_float()),
+        (a, b) => Some(a.cmp(b)),
+    })
+}
+
+define_op!(OP_EQ, 2, false, commutative: true, returns: ReturnType::Always(ValueType::Bool));
+pub(crate) fn op_eq(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(values_eq(&args[0], &args[1])))
+}
+
+define_op!(OP_NULLIF, 2, false);
+// null if the arguments are equal as in `==`, otherwise the first argument
+pub(crate) fn op_nullif(args: &[DataValue]) -> Result<DataValue> {
+    Ok(if values_eq(&args[0], &args[1]) {
+        DataValue::Null
+    } else {
+        args[0].clone()
+    })
+}
+
+define_op!(OP_IS_UUID, 1, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_is_uuid(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(matches!(args[0], DataValue::Uuid(_))))
 }
 
-define_op!(OP_IS_IN, 2, false);
+define_op!(OP_IS_IN, 2, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_is_in(args: &[DataValue]) -> Result<DataValue> {
     let left = &args[0];
     let right = args[1]
@@ -101,160 +172,347 @@ pub(crate) fn op_is_in(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(right.contains(left)))
 }
 
-define_op!(OP_NEQ, 2, false);
+define_op!(OP_IN, 2, false, returns: ReturnType::Always(ValueType::Bool));
+// elements are compared as in `==`, so `null in [null]` is true
+pub(crate) fn op_in(args: &[DataValue]) -> Result<DataValue> {
+    let right = args[1]
+        .get_slice()
+        .ok_or_else(|| miette!("right hand side of 'in' must be a list"))?;
+    Ok(DataValue::from(
+        right.iter().any(|el| values_eq(&args[0], el)),
+    ))
+}
+
+define_op!(OP_NEQ, 2, false, commutative: true, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_neq(args: &[DataValue]) -> Result<DataValue> {
-    Ok(DataValue::from(match (&args[0], &args[1]) {
-        (DataValue::Num(Num::Float(f)), DataValue::Num(Num::Int(i)))
-        | (DataValue::Num(Num::Int(i)), DataValue::Num(Num::Float(f))) => *i as f64 != *f,
-        (a, b) => a != b,
-    }))
+    Ok(DataValue::from(!values_eq(&args[0], &args[1])))
 }
 
-define_op!(OP_GT, 2, false);
+define_op!(OP_GT, 2, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_gt(args: &[DataValue]) -> Result<DataValue> {
-    ensure_same_value_type(&args[0], &args[1])?;
-    Ok(DataValue::from(match (&args[0], &args[1]) {
-        (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l > *r as f64,
-        (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => *l as f64 > *r,
-        (a, b) => a > b,
-    }))
+    Ok(DataValue::from(matches!(
+        compare_values(&args[0], &args[1])?,
+        Some(Ordering::Greater)
+    )))
 }
 
-define_op!(OP_GE, 2, false);
+define_op!(OP_GE, 2, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_ge(args: &[DataValue]) -> Result<DataValue> {
-    ensure_same_value_type(&args[0], &args[1])?;
-    Ok(DataValue::from(match (&args[0], &args[1]) {
-        (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l >= *r as f64,
-        (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => *l as f64 >= *r,
-        (a, b) => a >= b,
-    }))
+    Ok(DataValue::from(matches!(
+        compare_values(&args[0], &args[1])?,
+        Some(Ordering::Greater | Ordering::Equal)
+    )))
 }
 
-define_op!(OP_LT, 2, false);
+define_op!(OP_LT, 2, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_lt(args: &[DataValue]) -> Result<DataValue> {
-    ensure_same_value_type(&args[0], &args[1])?;
-    Ok(DataValue::from(match (&args[0], &args[1]) {
-        (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l < (*r as f64),
-        (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => (*l as f64) < *r,
-        (a, b) => a < b,
-    }))
+    Ok(DataValue::from(matches!(
+        compare_values(&args[0], &args[1])?,
+        Some(Ordering::Less)
+    )))
+}
+
+fn values_le(a: &DataValue, b: &DataValue) -> Result<bool> {
+    Ok(matches!(
+        compare_values(a, b)?,
+        Some(Ordering::Less | Ordering::Equal)
+    ))
 }
 
-define_op!(OP_LE, 2, false);
+define_op!(OP_LE, 2, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_le(args: &[DataValue]) -> Result<DataValue> {
-    ensure_same_value_type(&args[0], &args[1])?;
-    Ok(DataValue::from(match (&args[0], &args[1]) {
-        (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l <= (*r as f64),
-        (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => (*l as f64) <= *r,
-        (a, b) => a <= b,
-    }))
+    Ok(DataValue::from(values_le(&args[0], &args[1])?))
+}
+
+define_op!(OP_CMP, 2, false, returns: ReturnType::Always(ValueType::Int));
+// -1, 0 or 1 as in `<`, `==` and `>`, but total: values of different types are ranked by type with
+// null lowest, and NaN is above all other numbers
+pub(crate) fn op_cmp(args: &[DataValue]) -> Result<DataValue> {
+    let ord = match (&args[0], &args[1]) {
+        (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Int(r))) => l.cmp(r),
+        (DataValue::Num(l), DataValue::Num(r)) => {
+            let (l, r) = (l.get_float(), r.get_float());
+            l.partial_cmp(&r).unwrap_or_else(|| l.total_cmp(&r))
+        }
+        (a, b) => a.cmp(b),
+    };
+    Ok(DataValue::from(ord as i64))
+}
+
+define_op!(OP_BETWEEN, 3, false, returns: ReturnType::Always(ValueType::Bool));
+pub(crate) fn op_between(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(
+        values_le(&args[1], &args[0])? && values_le(&args[0], &args[2])?,
+    ))
 }
 
-define_op!(OP_ADD, 0, true);
+/// Numeric operands lifted to a common form: floats as soon as either side is a float
+enum NumPair {
+    Int(i64, i64),
+    Float(f64, f64),
+}
+
+fn promote_pair(a: &DataValue, b: &DataValue) -> Option<NumPair> {
+    Some(match (a, b) {
+        (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => NumPair::Int(*a, *b),
+        (DataValue::Num(a), DataValue::Num(b)) => NumPair::Float(a.get_float(), b.get_float()),
+        _ => return None,
+    })
+}
+
+/// How the arithmetic operators (`+`, `-`, `*`, `mod`, and friends) treat a pair of numeric operands.
+/// Division is the exception: it always produces a float.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NumericPromotion {
+    /// Both operands are ints and the result is an int
+    IntInt,
+    /// One operand is an int and is converted to a float, the result is a float
+    IntFloat,
+    /// Both operands are floats and the result is a float
+    FloatFloat,
+}
+
+/// Report how `a` and `b` would be promoted by the arithmetic operators, without evaluating anything.
+/// Returns `None` if either value is not a number.
+pub fn numeric_promotion(a: &DataValue, b: &DataValue) -> Option<NumericPromotion> {
+    Some(match (a, b) {
+        (DataValue::Num(Num::Int(_)), DataValue::Num(Num::Int(_))) => NumericPromotion::IntInt,
+        (DataValue::Num(Num::Float(_)), DataValue::Num(Num::Float(_))) => {
+            NumericPromotion::FloatFloat
+        }
+        (DataValue::Num(_), DataValue::Num(_)) => NumericPromotion::IntFloat,
+        _ => return None,
+    })
+}
+
+define_op!(
+    OP_ADD,
+    0,
+    true,
+    commutative: true,
+    associative: true,
+    identity: Some(DataValue::Num(Num::Int(0))),
+    returns: ReturnType::Promoted,
+);
 pub(crate) fn op_add(args: &[DataValue]) -> Result<DataValue> {
-    let mut i_accum = 0i64;
-    let mut f_accum = 0.0f64;
-    for arg in args {
-        match arg {
-            DataValue::Num(Num::Int(i)) => i_accum += i,
-            DataValue::Num(Num::Float(f)) => f_accum += f,
-            _ => bail!("addition requires numbers"),
+    args.iter().try_fold(DataValue::from(0), |accum, nxt| {
+        match promote_pair(&accum, nxt) {
+            Some(NumPair::Int(a, b)) => Ok(DataValue::from(a + b)),
+            Some(NumPair::Float(a, b)) => Ok(DataValue::from(a + b)),
+            None => bail!("addition requires numbers"),
+        }
+    })
+}
+
+// `max` and `min` give NaN if any argument is NaN, whereas their `_ignore_nan` variants skip NaN
+// arguments and only give NaN if every argument is NaN. Without arguments that are not NaN
+// the result is the identity, `-inf` for `max` and `inf` for `min`.
+fn numeric_extreme(
+    args: &[DataValue],
+    name: &'static str,
+    wanted: Ordering,
+    skip_nan: bool,
+) -> Result<Option<Num>> {
+    let mut ret: Option<Num> = None;
+    let mut has_nan = false;
+    for (i, arg) in args.iter().enumerate() {
+        let n = match arg {
+            DataValue::Num(n) => *n,
+            _ => bail!(ArgTypeError::new(name, i, "numbers")),
+        };
+        if matches!(n, Num::Float(f) if f.is_nan()) {
+            has_nan = true;
+            continue;
+        }
+        match ret {
+            Some(cur) if n.cmp(&cur) != wanted => {}
+            _ => ret = Some(n),
         }
     }
-    if f_accum == 0.0f64 {
-        Ok(DataValue::Num(Num::Int(i_accum)))
-    } else {
-        Ok(DataValue::Num(Num::Float(i_accum as f64 + f_accum)))
+    if has_nan && (!skip_nan || ret.is_none()) {
+        return Ok(Some(Num::Float(f64::NAN)));
     }
+    Ok(ret)
 }
 
-define_op!(OP_MAX, 1, true);
+define_op!(OP_MAX, 1, true, commutative: true, associative: true, returns: ReturnType::Uniform);
 pub(crate) fn op_max(args: &[DataValue]) -> Result<DataValue> {
-    let res = args
-        .iter()
-        .try_fold(None, |accum, nxt| match (accum, nxt) {
-            (None, d @ DataValue::Num(_)) => Ok(Some(d.clone())),
-            (Some(DataValue::Num(a)), DataValue::Num(b)) => Ok(Some(DataValue::Num(a.max(*b)))),
-            _ => bail!("'max can only be applied to numbers'"),
-        })?;
-    match res {
-        None => Ok(DataValue::Num(Num::Float(f64::NEG_INFINITY))),
-        Some(v) => Ok(v),
-    }
+    let res = numeric_extreme(args, "max", Ordering::Greater, false)?;
+    Ok(DataValue::Num(res.unwrap_or(Num::Float(f64::NEG_INFINITY))))
 }
 
-define_op!(OP_MIN, 1, true);
+define_op!(
+    OP_MAX_IGNORE_NAN,
+    1,
+    true,
+    commutative: true,
+    associative: true,
+    returns: ReturnType::Uniform,
+);
+pub(crate) fn op_max_ignore_nan(args: &[DataValue]) -> Result<DataValue> {
+    let res = numeric_extreme(args, "max_ignore_nan", Ordering::Greater, true)?;
+    Ok(DataValue::Num(res.unwrap_or(Num::Float(f64::NEG_INFINITY))))
+}
+
+define_op!(OP_MIN, 1, true, commutative: true, associative: true, returns: ReturnType::Uniform);
 pub(crate) fn op_min(args: &[DataValue]) -> Result<DataValue> {
-    let res = args
-        .iter()
-        .try_fold(None, |accum, nxt| match (accum, nxt) {
-            (None, d @ DataValue::Num(_)) => Ok(Some(d.clone())),
-            (Some(DataValue::Num(a)), DataValue::Num(b)) => Ok(Some(DataValue::Num(a.min(*b)))),
-            _ => bail!("'min' can only be applied to numbers"),
-        })?;
-    match res {
-        None => Ok(DataValue::Num(Num::Float(f64::INFINITY))),
-        Some(v) => Ok(v),
+    let res = numeric_extreme(args, "min", Ordering::Less, false)?;
+    Ok(DataValue::Num(res.unwrap_or(Num::Float(f64::INFINITY))))
+}
+
+define_op!(
+    OP_MIN_IGNORE_NAN,
+    1,
+    true,
+    commutative: true,
+    associative: true,
+    returns: ReturnType::Uniform,
+);
+pub(crate) fn op_min_ignore_nan(args: &[DataValue]) -> Result<DataValue> {
+    let res = numeric_extreme(args, "min_ignore_nan", Ordering::Less, true)?;
+    Ok(DataValue::Num(res.unwrap_or(Num::Float(f64::INFINITY))))
+}
+
+// unlike `max` and `min`, `greatest` and `least` accept any comparable values and skip nulls,
+// giving null only if every argument is null
+fn extreme_value(args: &[DataValue], wanted: Ordering) -> Result<DataValue> {
+    let mut ret: Option<&DataValue> = None;
+    for arg in args {
+        if *arg == DataValue::Null {
+            continue;
+        }
+        match ret {
+            Some(cur) if compare_values(arg, cur)? != Some(wanted) => {}
+            _ => ret = Some(arg),
+        }
+    }
+    Ok(ret.cloned().unwrap_or(DataValue::Null))
+}
+
+define_op!(OP_GREATEST, 1, true);
+pub(crate) fn op_greatest(args: &[DataValue]) -> Result<DataValue> {
+    extreme_value(args, Ordering::Greater)
+}
+
+define_op!(OP_LEAST, 1, true);
+pub(crate) fn op_least(args: &[DataValue]) -> Result<DataValue> {
+    extreme_value(args, Ordering::Less)
+}
+
+// `sum`, `product` and `avg` reduce a single list, unlike the aggregations of the same names
+fn numeric_list<'a>(arg: &'a DataValue, name: &'static str) -> Result<&'a [DataValue]> {
+    let l = arg
+        .get_slice()
+        .ok_or_else(|| ArgTypeError::new(name, 0, "lists"))?;
+    ensure!(
+        l.iter().all(|v| matches!(v, DataValue::Num(_))),
+        "'{}' requires a list of numbers",
+        name
+    );
+    Ok(l)
+}
+
+// an empty list reduces to the identity element of `op`
+fn reduce_list(arg: &DataValue, op: &Op, name: &'static str) -> Result<DataValue> {
+    let l = numeric_list(arg, name)?;
+    match l.split_first() {
+        None => op
+            .identity_element()
+            .ok_or_else(|| miette!("'{}' cannot reduce an empty list", name)),
+        Some((first, rest)) => rest
+            .iter()
+            .try_fold(first.clone(), |acc, v| (op.inner)(&[acc, v.clone()])),
     }
 }
 
-define_op!(OP_SUB, 2, false);
+define_op!(OP_SUM, 1, false, returns: ReturnType::Always(ValueType::Number));
+pub(crate) fn op_sum(args: &[DataValue]) -> Result<DataValue> {
+    reduce_list(&args[0], &OP_ADD, "sum")
+}
+
+define_op!(OP_PRODUCT, 1, false, returns: ReturnType::Always(ValueType::Number));
+pub(crate) fn op_product(args: &[DataValue]) -> Result<DataValue> {
+    reduce_list(&args[0], &OP_MUL, "product")
+}
+
+// the average of an empty list is null
+define_op!(OP_AVG, 1, false);
+pub(crate) fn op_avg(args: &[DataValue]) -> Result<DataValue> {
+    let l = numeric_list(&args[0], "avg")?;
+    if l.is_empty() {
+        return Ok(DataValue::Null);
+    }
+    let total: f64 = l.iter().filter_map(|v| v.get_float()).sum();
+    Ok(DataValue::from(total / l.len() as f64))
+}
+
+define_op!(OP_SUB, 2, false, returns: ReturnType::Promoted);
 pub(crate) fn op_sub(args: &[DataValue]) -> Result<DataValue> {
-    Ok(match (&args[0], &args[1]) {
-        (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
-            DataValue::Num(Num::Int(*a - *b))
-        }
-        (DataValue::Num(Num::Float(a)), DataValue::Num(Num::Float(b))) => {
-            DataValue::Num(Num::Float(*a - *b))
-        }
-        (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Float(b))) => {
-            DataValue::Num(Num::Float((*a as f64) - b))
-        }
-        (DataValue::Num(Num::Float(a)), DataValue::Num(Num::Int(b))) => {
-            DataValue::Num(Num::Float(a - (*b as f64)))
-        }
-        _ => bail!("subtraction requires numbers"),
+    Ok(match promote_pair(&args[0], &args[1]) {
+        Some(NumPair::Int(a, b)) => DataValue::from(a - b),
+        Some(NumPair::Float(a, b)) => DataValue::from(a - b),
+        None => bail!("subtraction requires numbers"),
     })
 }
 
-define_op!(OP_MUL, 0, true);
+define_op!(
+    OP_MUL,
+    0,
+    true,
+    commutative: true,
+    associative: true,
+    identity: Some(DataValue::Num(Num::Int(1))),
+    returns: ReturnType::Promoted,
+);
 pub(crate) fn op_mul(args: &[DataValue]) -> Result<DataValue> {
-    let mut i_accum = 1i64;
-    let mut f_accum = 1.0f64;
-    for arg in args {
-        match arg {
-            DataValue::Num(Num::Int(i)) => i_accum *= i,
-            DataValue::Num(Num::Float(f)) => f_accum *= f,
-            _ => bail!("multiplication requires numbers"),
+    args.iter().try_fold(DataValue::from(1), |accum, nxt| {
+        match promote_pair(&accum, nxt) {
+            Some(NumPair::Int(a, b)) => Ok(DataValue::from(a * b)),
+            Some(NumPair::Float(a, b)) => Ok(DataValue::from(a * b)),
+            None => bail!("multiplication requires numbers"),
         }
-    }
-    if f_accum == 1.0f64 {
-        Ok(DataValue::Num(Num::Int(i_accum)))
-    } else {
-        Ok(DataValue::Num(Num::Float(i_accum as f64 * f_accum)))
-    }
+    })
 }
 
-define_op!(OP_DIV, 2, false);
+define_op!(OP_DIV, 2, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_div(args: &[DataValue]) -> Result<DataValue> {
-    Ok(match (&args[0], &args[1]) {
-        (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
-            DataValue::Num(Num::Float((*a as f64) / (*b as f64)))
-        }
-        (DataValue::Num(Num::Float(a)), DataValue::Num(Num::Float(b))) => {
-            DataValue::Num(Num::Float(*a / *b))
-        }
-        (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Float(b))) => {
-            DataValue::Num(Num::Float((*a as f64) / b))
+    Ok(match promote_pair(&args[0], &args[1]) {
+        Some(NumPair::Int(a, b)) => DataValue::from(a as f64 / b as f64),
+        Some(NumPair::Float(a, b)) => DataValue::from(a / b),
+        None => bail!("division requires numbers"),
+    })
+}
+
+define_op!(OP_TRY_DIV, 2, false);
+pub(crate) fn op_try_div(args: &[DataValue]) -> Result<DataValue> {
+    match &args[1] {
+        DataValue::Num(n) if n.get_float() == 0. => {
+            ensure!(
+                matches!(args[0], DataValue::Num(_)),
+                "division requires numbers"
+            );
+            Ok(DataValue::Null)
         }
-        (DataValue::Num(Num::Float(a)), DataValue::Num(Num::Int(b))) => {
-            DataValue::Num(Num::Float(a / (*b as f64)))
+        _ => op_div(args),
+    }
+}
+
+define_op!(OP_INT_DIV, 2, false, returns: ReturnType::Always(ValueType::Int));
+/// Integer division truncating toward zero, so `int_div(-7, 2) == -3` (not `-4` as flooring gives)
+pub(crate) fn op_int_div(args: &[DataValue]) -> Result<DataValue> {
+    match (&args[0], &args[1]) {
+        (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
+            ensure!(*b != 0, "'int_div' cannot divide by zero");
+            let res = a
+                .checked_div(*b)
+                .ok_or_else(|| miette!("integer overflow in 'int_div'"))?;
+            Ok(DataValue::from(res))
         }
-        _ => bail!("division requires numbers"),
-    })
+        _ => bail!("'int_div' requires integers"),
+    }
 }
 
-define_op!(OP_MINUS, 1, false);
+define_op!(OP_MINUS, 1, false, returns: ReturnType::Promoted);
 pub(crate) fn op_minus(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(-(*i))),
@@ -263,16 +521,16 @@ pub(crate) fn op_minus(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
-define_op!(OP_ABS, 1, false);
+define_op!(OP_ABS, 1, false, returns: ReturnType::Promoted);
 pub(crate) fn op_abs(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(i.abs())),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.abs())),
-        _ => bail!("'abs' requires numbers"),
+        _ => bail!(ArgTypeError::new("abs", 0, "numbers")),
     })
 }
 
-define_op!(OP_SIGNUM, 1, false);
+define_op!(OP_SIGNUM, 1, false, returns: ReturnType::Always(ValueType::Number));
 pub(crate) fn op_signum(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(i.signum())),
@@ -287,239 +545,264 @@ pub(crate) fn op_signum(args: &[DataValue]) -> Result<DataValue> {
                 DataValue::from(f64::NAN)
             }
         }
-        _ => bail!("'signum' requires numbers"),
+        _ => bail!(ArgTypeError::new("signum", 0, "numbers")),
     })
 }
 
-define_op!(OP_FLOOR, 1, false);
+define_op!(OP_FLOOR, 1, false, returns: ReturnType::Promoted);
 pub(crate) fn op_floor(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(*i)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.floor())),
-        _ => bail!("'floor' requires numbers"),
+        _ => bail!(ArgTypeError::new("floor", 0, "numbers")),
     })
 }
 
-define_op!(OP_CEIL, 1, false);
+define_op!(OP_CEIL, 1, false, returns: ReturnType::Promoted);
 pub(crate) fn op_ceil(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(*i)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.ceil())),
-        _ => bail!("'ceil' requires numbers"),
+        _ => bail!(ArgTypeError::new("ceil", 0, "numbers")),
     })
 }
 
-define_op!(OP_ROUND, 1, false);
+define_op!(OP_ROUND, 1, false, returns: ReturnType::Promoted);
 pub(crate) fn op_round(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(*i)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.round())),
-        _ => bail!("'round' requires numbers"),
+        _ => bail!(ArgTypeError::new("round", 0, "numbers")),
     })
 }
 
-define_op!(OP_EXP, 1, false);
+define_op!(OP_EXP, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_exp(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'exp' requires numbers"),
+        _ => bail!(ArgTypeError::new("exp", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.exp())))
 }
 
-define_op!(OP_EXP2, 1, false);
+define_op!(OP_EXP2, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_exp2(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'exp2' requires numbers"),
+        _ => bail!(ArgTypeError::new("exp2", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.exp2())))
 }
 
-define_op!(OP_LN, 1, false);
+define_op!(OP_LN, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_ln(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'ln' requires numbers"),
+        _ => bail!(ArgTypeError::new("ln", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.ln())))
 }
 
-define_op!(OP_LOG2, 1, false);
+define_op!(OP_LOG2, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_log2(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'log2' requires numbers"),
+        _ => bail!(ArgTypeError::new("log2", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.log2())))
 }
 
-define_op!(OP_LOG10, 1, false);
+define_op!(OP_LOG10, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_log10(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'log10' requires numbers"),
+        _ => bail!(ArgTypeError::new("log10", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.log10())))
 }
 
-define_op!(OP_SIN, 1, false);
+define_op!(OP_SIN, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_sin(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'sin' requires numbers"),
+        _ => bail!(ArgTypeError::new("sin", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.sin())))
 }
 
-define_op!(OP_COS, 1, false);
+define_op!(OP_COS, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_cos(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'cos' requires numbers"),
+        _ => bail!(ArgTypeError::new("cos", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.cos())))
 }
 
-define_op!(OP_TAN, 1, false);
+define_op!(OP_TAN, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_tan(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'tan' requires numbers"),
+        _ => bail!(ArgTypeError::new("tan", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.tan())))
 }
 
-define_op!(OP_ASIN, 1, false);
+define_op!(OP_ASIN, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_asin(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'asin' requires numbers"),
+        _ => bail!(ArgTypeError::new("asin", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.asin())))
 }
 
-define_op!(OP_ACOS, 1, false);
+define_op!(OP_ACOS, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_acos(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'acos' requires numbers"),
+        _ => bail!(ArgTypeError::new("acos", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.acos())))
 }
 
-define_op!(OP_ATAN, 1, false);
+define_op!(OP_ATAN, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_atan(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'atan' requires numbers"),
+        _ => bail!(ArgTypeError::new("atan", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.atan())))
 }
 
-define_op!(OP_ATAN2, 2, false);
+define_op!(OP_ATAN2, 2, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_atan2(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'atan2' requires numbers"),
+        _ => bail!(ArgTypeError::new("atan2", 0, "numbers")),
     };
     let b = match &args[1] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'atan2' requires numbers"),
+        _ => bail!(ArgTypeError::new("atan2", 1, "numbers")),
     };
 
     Ok(DataValue::Num(Num::Float(a.atan2(b))))
 }
 
-define_op!(OP_SINH, 1, false);
+define_op!(OP_SINH, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_sinh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'sinh' requires numbers"),
+        _ => bail!(ArgTypeError::new("sinh", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.sinh())))
 }
 
-define_op!(OP_COSH, 1, false);
+define_op!(OP_COSH, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_cosh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'cosh' requires numbers"),
+        _ => bail!(ArgTypeError::new("cosh", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.cosh())))
 }
 
-define_op!(OP_TANH, 1, false);
+define_op!(OP_TANH, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_tanh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'tanh' requires numbers"),
+        _ => bail!(ArgTypeError::new("tanh", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.tanh())))
 }
 
-define_op!(OP_ASINH, 1, false);
+define_op!(OP_ASINH, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_asinh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'asinh' requires numbers"),
+        _ => bail!(ArgTypeError::new("asinh", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.asinh())))
 }
 
-define_op!(OP_ACOSH, 1, false);
+define_op!(OP_ACOSH, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_acosh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'acosh' requires numbers"),
+        _ => bail!(ArgTypeError::new("acosh", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.acosh())))
 }
 
-define_op!(OP_ATANH, 1, false);
+define_op!(OP_ATANH, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_atanh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'atanh' requires numbers"),
+        _ => bail!(ArgTypeError::new("atanh", 0, "numbers")),
     };
     Ok(DataValue::Num(Num::Float(a.atanh())))
 }
 
-define_op!(OP_POW, 2, false);
+define_op!(OP_POW, 2, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_pow(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'pow' requires numbers"),
+        _ => bail!(ArgTypeError::new("pow", 0, "numbers")),
     };
     let b = match &args[1] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
-        _ => bail!("'pow' requires numbers"),
+        _ => bail!(ArgTypeError::new("pow", 1, "numbers")),
     };
+    ensure!(
+        !(a == 0. && b < 0.),
+        "'pow' cannot raise zero to a negative power"
+    );
     Ok(DataValue::Num(Num::Float(a.powf(b))))
 }
 
-define_op!(OP_MOD, 2, false);
+define_op!(OP_LERP, 3, false, returns: ReturnType::Always(ValueType::Float));
+// `t` outside of [0, 1] extrapolates
+pub(crate) fn op_lerp(args: &[DataValue]) -> Result<DataValue> {
+    let mut xs = [0.; 3];
+    for (i, (x, arg)) in xs.iter_mut().zip(args).enumerate() {
+        *x = arg
+            .get_float()
+            .ok_or_else(|| ArgTypeError::new("lerp", i, "numbers"))?;
+    }
+    let [a, b, t] = xs;
+    Ok(DataValue::from(a + (b - a) * t))
+}
+
+define_op!(OP_CLAMP01, 1, false, returns: ReturnType::Always(ValueType::Float));
+pub(crate) fn op_clamp01(args: &[DataValue]) -> Result<DataValue> {
+    let x = args[0]
+        .get_float()
+        .ok_or_else(|| ArgTypeError::new("clamp01", 0, "numbers"))?;
+    Ok(DataValue::from(x.clamp(0., 1.)))
+}
+
+define_op!(OP_MOD, 2, false, returns: ReturnType::Promoted);
 pub(crate) fn op_mod(args: &[DataValue]) -> Result<DataValue> {
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
@@ -538,42 +821,146 @@ pub(crate) fn op_mod(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
-define_op!(OP_AND, 0, true);
+fn int_pair(args: &[DataValue], name: &'static str) -> Result<(u64, u64)> {
+    let a = match &args[0] {
+        DataValue::Num(Num::Int(a)) => a.unsigned_abs(),
+        _ => bail!(ArgTypeError::new(name, 0, "integers")),
+    };
+    let b = match &args[1] {
+        DataValue::Num(Num::Int(b)) => b.unsigned_abs(),
+        _ => bail!(ArgTypeError::new(name, 1, "integers")),
+    };
+    Ok((a, b))
+}
+
+fn gcd_u64(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+// `gcd` and `lcm` work on absolute values, so the results are never negative
+define_op!(
+    OP_GCD,
+    2,
+    false,
+    commutative: true,
+    associative: true,
+    returns: ReturnType::Always(ValueType::Int),
+);
+pub(crate) fn op_gcd(args: &[DataValue]) -> Result<DataValue> {
+    let (a, b) = int_pair(args, "gcd")?;
+    let res = i64::try_from(gcd_u64(a, b)).map_err(|_| miette!("integer overflow in 'gcd'"))?;
+    Ok(DataValue::from(res))
+}
+
+define_op!(
+    OP_LCM,
+    2,
+    false,
+    commutative: true,
+    associative: true,
+    returns: ReturnType::Always(ValueType::Int),
+);
+pub(crate) fn op_lcm(args: &[DataValue]) -> Result<DataValue> {
+    let (a, b) = int_pair(args, "lcm")?;
+    if a == 0 || b == 0 {
+        return Ok(DataValue::from(0));
+    }
+    let res = (a / gcd_u64(a, b))
+        .checked_mul(b)
+        .and_then(|l| i64::try_from(l).ok())
+        .ok_or_else(|| miette!("integer overflow in 'lcm'"))?;
+    Ok(DataValue::from(res))
+}
+
+// `and` and `or` follow three-valued logic: null means unknown
+define_op!(
+    OP_AND,
+    0,
+    true,
+    commutative: true,
+    associative: true,
+    identity: Some(DataValue::Bool(true)),
+    returns: ReturnType::Logical,
+);
 pub(crate) fn op_and(args: &[DataValue]) -> Result<DataValue> {
+    let mut ret = DataValue::from(true);
     for arg in args {
-        if !arg
-            .get_bool()
-            .ok_or_else(|| miette!("'and' requires booleans"))?
-        {
-            return Ok(DataValue::from(false));
+        match arg {
+            DataValue::Bool(false) => return Ok(DataValue::from(false)),
+            DataValue::Bool(true) => {}
+            DataValue::Null => ret = DataValue::Null,
+            _ => bail!("'and' requires booleans"),
         }
     }
-    Ok(DataValue::from(true))
-}
-
-define_op!(OP_OR, 0, true);
+    Ok(ret)
+}
+
+define_op!(
+    OP_OR,
+    0,
+    true,
+    commutative: true,
+    associative: true,
+    identity: Some(DataValue::Bool(false)),
+    returns: ReturnType::Logical,
+);
 pub(crate) fn op_or(args: &[DataValue]) -> Result<DataValue> {
+    let mut ret = DataValue::from(false);
     for arg in args {
-        if arg
-            .get_bool()
-            .ok_or_else(|| miette!("'or' requires booleans"))?
-        {
-            return Ok(DataValue::from(true));
+        match arg {
+            DataValue::Bool(true) => return Ok(DataValue::from(true)),
+            DataValue::Bool(false) => {}
+            DataValue::Null => ret = DataValue::Null,
+            _ => bail!("'or' requires booleans"),
+        }
+    }
+    Ok(ret)
+}
+
+// like `and` and `or`, null means unknown, so any null argument makes the result null
+define_op!(
+    OP_XOR,
+    2,
+    false,
+    commutative: true,
+    associative: true,
+    identity: Some(DataValue::Bool(false)),
+    returns: ReturnType::Logical,
+);
+pub(crate) fn op_xor(args: &[DataValue]) -> Result<DataValue> {
+    let mut ret = DataValue::from(false);
+    for (i, arg) in args.iter().enumerate() {
+        match (arg, &ret) {
+            (DataValue::Bool(b), DataValue::Bool(acc)) => ret = DataValue::from(*b != *acc),
+            (DataValue::Bool(_), _) => {}
+            (DataValue::Null, _) => ret = DataValue::Null,
+            _ => bail!(ArgTypeError::new("xor", i, "booleans")),
         }
     }
-    Ok(DataValue::from(false))
+    Ok(ret)
 }
 
-define_op!(OP_NEGATE, 1, false);
+// the negation of unknown is unknown
+define_op!(OP_NEGATE, 1, false, returns: ReturnType::Logical);
 pub(crate) fn op_negate(args: &[DataValue]) -> Result<DataValue> {
-    if let DataValue::Bool(b) = &args[0] {
-        Ok(DataValue::from(!*b))
-    } else {
-        bail!("'negate' requires booleans");
+    match &args[0] {
+        DataValue::Bool(b) => Ok(DataValue::from(!*b)),
+        DataValue::Null => Ok(DataValue::Null),
+        _ => bail!(ArgTypeError::new("negate", 0, "booleans")),
     }
 }
 
-define_op!(OP_BIT_AND, 2, false);
+define_op!(
+    OP_BIT_AND,
+    2,
+    false,
+    commutative: true,
+    associative: true,
+    returns: ReturnType::Always(ValueType::Bytes),
+);
 pub(crate) fn op_bit_and(args: &[DataValue]) -> Result<DataValue> {
     match (&args[0], &args[1]) {
         (DataValue::Bytes(left), DataValue::Bytes(right)) => {
@@ -591,7 +978,14 @@ pub(crate) fn op_bit_and(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_BIT_OR, 2, false);
+define_op!(
+    OP_BIT_OR,
+    2,
+    false,
+    commutative: true,
+    associative: true,
+    returns: ReturnType::Always(ValueType::Bytes),
+);
 pub(crate) fn op_bit_or(args: &[DataValue]) -> Result<DataValue> {
     match (&args[0], &args[1]) {
         (DataValue::Bytes(left), DataValue::Bytes(right)) => {
@@ -609,7 +1003,7 @@ pub(crate) fn op_bit_or(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_BIT_NOT, 1, false);
+define_op!(OP_BIT_NOT, 1, false, returns: ReturnType::Always(ValueType::Bytes));
 pub(crate) fn op_bit_not(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
         DataValue::Bytes(arg) => {
@@ -623,7 +1017,14 @@ pub(crate) fn op_bit_not(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_BIT_XOR, 2, false);
+define_op!(
+    OP_BIT_XOR,
+    2,
+    false,
+    commutative: true,
+    associative: true,
+    returns: ReturnType::Always(ValueType::Bytes),
+);
 pub(crate) fn op_bit_xor(args: &[DataValue]) -> Result<DataValue> {
     match (&args[0], &args[1]) {
         (DataValue::Bytes(left), DataValue::Bytes(right)) => {
@@ -641,7 +1042,33 @@ pub(crate) fn op_bit_xor(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_UNPACK_BITS, 1, false);
+// `bit_count`, `leading_zeros` and `trailing_zeros` look at the 64-bit two's complement
+// pattern of an int, so `bit_count(-1)` is 64
+define_op!(OP_BIT_COUNT, 1, false, returns: ReturnType::Always(ValueType::Int));
+pub(crate) fn op_bit_count(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Num(Num::Int(i)) => Ok(DataValue::from(i.count_ones() as i64)),
+        _ => bail!(ArgTypeError::new("bit_count", 0, "integers")),
+    }
+}
+
+define_op!(OP_LEADING_ZEROS, 1, false, returns: ReturnType::Always(ValueType::Int));
+pub(crate) fn op_leading_zeros(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Num(Num::Int(i)) => Ok(DataValue::from(i.leading_zeros() as i64)),
+        _ => bail!(ArgTypeError::new("leading_zeros", 0, "integers")),
+    }
+}
+
+define_op!(OP_TRAILING_ZEROS, 1, false, returns: ReturnType::Always(ValueType::Int));
+pub(crate) fn op_trailing_zeros(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Num(Num::Int(i)) => Ok(DataValue::from(i.trailing_zeros() as i64)),
+        _ => bail!(ArgTypeError::new("trailing_zeros", 0, "integers")),
+    }
+}
+
+define_op!(OP_UNPACK_BITS, 1, false, returns: ReturnType::Always(ValueType::List));
 pub(crate) fn op_unpack_bits(args: &[DataValue]) -> Result<DataValue> {
     if let DataValue::Bytes(bs) = &args[0] {
         let mut ret = vec![false; bs.len() * 8];
@@ -663,7 +1090,7 @@ pub(crate) fn op_unpack_bits(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_PACK_BITS, 1, false);
+define_op!(OP_PACK_BITS, 1, false, returns: ReturnType::Always(ValueType::Bytes));
 pub(crate) fn op_pack_bits(args: &[DataValue]) -> Result<DataValue> {
     if let DataValue::List(v) = &args[0] {
         let l = (v.len() as f64 / 8.).ceil() as usize;
@@ -700,38 +1127,38 @@ pub(crate) fn op_pack_bits(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_CONCAT, 1, true);
+define_op!(OP_CONCAT, 1, true, associative: true, returns: ReturnType::SameAsFirst);
 pub(crate) fn op_concat(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
         DataValue::Str(_) => {
             let mut ret: String = Default::default();
-            for arg in args {
+            for (i, arg) in args.iter().enumerate() {
                 if let DataValue::Str(s) = arg {
                     ret += s;
                 } else {
-                    bail!("'concat' requires strings, or lists");
+                    bail!(ArgTypeError::new("concat", i, "strings"));
                 }
             }
             Ok(DataValue::from(ret))
         }
         DataValue::List(_) | DataValue::Set(_) => {
             let mut ret = vec![];
-            for arg in args {
+            for (i, arg) in args.iter().enumerate() {
                 if let DataValue::List(l) = arg {
                     ret.extend_from_slice(l);
                 } else if let DataValue::Set(s) = arg {
                     ret.extend(s.iter().cloned());
                 } else {
-                    bail!("'concat' requires strings, or lists");
+                    bail!(ArgTypeError::new("concat", i, "lists"));
                 }
             }
             Ok(DataValue::List(ret))
         }
-        _ => bail!("'concat' requires strings, or lists"),
+        _ => bail!(ArgTypeError::new("concat", 0, "strings or lists")),
     }
 }
 
-define_op!(OP_STR_INCLUDES, 2, false);
+define_op!(OP_STR_INCLUDES, 2, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_str_includes(args: &[DataValue]) -> Result<DataValue> {
     match (&args[0], &args[1]) {
         (DataValue::Str(l), DataValue::Str(r)) => Ok(DataValue::from(l.find(r as &str).is_some())),
@@ -739,68 +1166,251 @@ pub(crate) fn op_str_includes(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_LOWERCASE, 1, false);
+define_op!(OP_INDEX_OF, 2, false, returns: ReturnType::Always(ValueType::Int));
+// the index counts chars in strings, and list elements are compared as in `==`
+pub(crate) fn op_index_of(args: &[DataValue]) -> Result<DataValue> {
+    let idx = match (&args[0], &args[1]) {
+        (DataValue::Str(s), DataValue::Str(needle)) => s
+            .find(needle as &str)
+            .map(|byte_idx| s[..byte_idx].chars().count()),
+        (DataValue::Str(_), _) => bail!(ArgTypeError::new("index_of", 1, "strings")),
+        (DataValue::List(l), needle) => l.iter().position(|el| values_eq(el, needle)),
+        _ => bail!(ArgTypeError::new("index_of", 0, "strings or lists")),
+    };
+    Ok(DataValue::from(idx.map_or(-1, |i| i as i64)))
+}
+
+define_op!(OP_STRING_COUNT, 2, false, returns: ReturnType::Always(ValueType::Int));
+// occurrences are counted without overlaps, and as in Python an empty needle is found before every
+// char and at the end, i.e. the char count plus one times
+pub(crate) fn op_string_count(args: &[DataValue]) -> Result<DataValue> {
+    let s = match &args[0] {
+        DataValue::Str(s) => s,
+        _ => bail!(ArgTypeError::new("string_count", 0, "strings")),
+    };
+    let n = match &args[1] {
+        DataValue::Str(needle) if needle.is_empty() => s.chars().count() + 1,
+        DataValue::Str(needle) => s.matches(needle as &str).count(),
+        _ => bail!(ArgTypeError::new("string_count", 1, "strings")),
+    };
+    Ok(DataValue::from(n as i64))
+}
+
+/// Limit on the product of the lengths of the strings compared by `levenshtein`,
+/// as its running time grows with it
+const MAX_EDIT_DISTANCE_CELLS: usize = 1 << 26;
+
+define_op!(OP_LEVENSHTEIN, 2, false, returns: ReturnType::Always(ValueType::Int));
+// the distance counts inserted, deleted and substituted chars
+pub(crate) fn op_levenshtein(args: &[DataValue]) -> Result<DataValue> {
+    let a = match &args[0] {
+        DataValue::Str(s) => s.chars().collect_vec(),
+        _ => bail!(ArgTypeError::new("levenshtein", 0, "strings")),
+    };
+    let b = match &args[1] {
+        DataValue::Str(s) => s.chars().collect_vec(),
+        _ => bail!(ArgTypeError::new("levenshtein", 1, "strings")),
+    };
+    ensure!(
+        a.len().saturating_mul(b.len()) <= MAX_EDIT_DISTANCE_CELLS,
+        "strings given to 'levenshtein' are too long: the product of their lengths exceeds {}",
+        MAX_EDIT_DISTANCE_CELLS
+    );
+    // distances from the prefixes of `a` to the current prefix of `b`
+    let mut row = (0..=a.len()).collect_vec();
+    for (j, cb) in b.iter().enumerate() {
+        let mut diag = row[0];
+        row[0] = j + 1;
+        for (i, ca) in a.iter().enumerate() {
+            let subst = diag + usize::from(ca != cb);
+            diag = row[i + 1];
+            row[i + 1] = subst.min(row[i] + 1).min(diag + 1);
+        }
+    }
+    Ok(DataValue::from(row[a.len()] as i64))
+}
+
+define_op!(OP_LOWERCASE, 1, false, returns: ReturnType::Always(ValueType::String));
 pub(crate) fn op_lowercase(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
         DataValue::Str(s) => Ok(DataValue::from(s.to_lowercase())),
-        _ => bail!("'lowercase' requires strings"),
+        _ => bail!(ArgTypeError::new("lowercase", 0, "strings")),
     }
 }
 
-define_op!(OP_UPPERCASE, 1, false);
+define_op!(OP_UPPERCASE, 1, false, returns: ReturnType::Always(ValueType::String));
 pub(crate) fn op_uppercase(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
         DataValue::Str(s) => Ok(DataValue::from(s.to_uppercase())),
-        _ => bail!("'uppercase' requires strings"),
+        _ => bail!(ArgTypeError::new("uppercase", 0, "strings")),
     }
 }
 
-define_op!(OP_TRIM, 1, false);
+define_op!(OP_CAPITALIZE, 1, false, returns: ReturnType::Always(ValueType::String));
+// a leading non-letter such as whitespace is kept, so only the rest is lowercased
+pub(crate) fn op_capitalize(args: &[DataValue]) -> Result<DataValue> {
+    let s = match &args[0] {
+        DataValue::Str(s) => s,
+        _ => bail!(ArgTypeError::new("capitalize", 0, "strings")),
+    };
+    let mut chars = s.chars();
+    let mut ret = String::with_capacity(s.len());
+    if let Some(first) = chars.next() {
+        ret.extend(first.to_uppercase());
+    }
+    ret.push_str(&chars.as_str().to_lowercase());
+    Ok(DataValue::from(ret))
+}
+
+define_op!(OP_TITLE_CASE, 1, false, returns: ReturnType::Always(ValueType::String));
+// the first char of every whitespace-separated word is uppercased and the rest is kept as it is
+pub(crate) fn op_title_case(args: &[DataValue]) -> Result<DataValue> {
+    let s = match &args[0] {
+        DataValue::Str(s) => s,
+        _ => bail!(ArgTypeError::new("title_case", 0, "strings")),
+    };
+    let mut ret = String::with_capacity(s.len());
+    let mut word_start = true;
+    for c in s.chars() {
+        if c.is_whitespace() {
+            ret.push(c);
+            word_start = true;
+        } else if word_start {
+            ret.extend(c.to_uppercase());
+            word_start = false;
+        } else {
+            ret.push(c);
+        }
+    }
+    Ok(DataValue::from(ret))
+}
+
+define_op!(OP_TRIM, 1, false, returns: ReturnType::Always(ValueType::String));
 pub(crate) fn op_trim(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
         DataValue::Str(s) => Ok(DataValue::from(s.trim())),
-        _ => bail!("'trim' requires strings"),
+        _ => bail!(ArgTypeError::new("trim", 0, "strings")),
     }
 }
 
-define_op!(OP_TRIM_START, 1, false);
+define_op!(OP_TRIM_START, 1, false, returns: ReturnType::Always(ValueType::String));
 pub(crate) fn op_trim_start(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
         DataValue::Str(s) => Ok(DataValue::from(s.trim_start())),
-        _ => bail!("'trim_start' requires strings"),
+        _ => bail!(ArgTypeError::new("trim_start", 0, "strings")),
     }
 }
 
-define_op!(OP_TRIM_END, 1, false);
+define_op!(OP_TRIM_END, 1, false, returns: ReturnType::Always(ValueType::String));
 pub(crate) fn op_trim_end(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
         DataValue::Str(s) => Ok(DataValue::from(s.trim_end())),
-        _ => bail!("'trim_end' requires strings"),
+        _ => bail!(ArgTypeError::new("trim_end", 0, "strings")),
+    }
+}
+
+/// Limit on the size in bytes of strings that ops build by repetition or padding,
+/// so that a small query cannot exhaust memory
+const MAX_BUILT_STR_LEN: usize = 1 << 26;
+
+define_op!(OP_STRING_REPEAT, 2, false, returns: ReturnType::Always(ValueType::String));
+pub(crate) fn op_string_repeat(args: &[DataValue]) -> Result<DataValue> {
+    let s = match &args[0] {
+        DataValue::Str(s) => s,
+        _ => bail!(ArgTypeError::new("string_repeat", 0, "strings")),
+    };
+    let n = match &args[1] {
+        DataValue::Num(Num::Int(n)) => *n,
+        _ => bail!(ArgTypeError::new("string_repeat", 1, "integers")),
+    };
+    ensure!(n >= 0, "'string_repeat' requires a non-negative count");
+    let len = usize::try_from(n)
+        .ok()
+        .and_then(|n| s.len().checked_mul(n))
+        .filter(|len| *len <= MAX_BUILT_STR_LEN);
+    ensure!(
+        len.is_some(),
+        "result of 'string_repeat' would be larger than {MAX_BUILT_STR_LEN} bytes"
+    );
+    Ok(DataValue::from(s.repeat(n as usize)))
+}
+
+define_op!(OP_PAD_LEFT, 3, false, returns: ReturnType::Always(ValueType::String));
+pub(crate) fn op_pad_left(args: &[DataValue]) -> Result<DataValue> {
+    pad_str(args, "pad_left", true)
+}
+
+define_op!(OP_PAD_RIGHT, 3, false, returns: ReturnType::Always(ValueType::String));
+pub(crate) fn op_pad_right(args: &[DataValue]) -> Result<DataValue> {
+    pad_str(args, "pad_right", false)
+}
+
+// width is counted in chars, and longer strings are returned unchanged
+fn pad_str(args: &[DataValue], name: &'static str, left: bool) -> Result<DataValue> {
+    let s = match &args[0] {
+        DataValue::Str(s) => s,
+        _ => bail!(ArgTypeError::new(name, 0, "strings")),
+    };
+    let width = args[1].get_non_neg_int().ok_or_else(|| {
+        miette!(
+            "second argument of '{}' must be a non-negative integer",
+            name
+        )
+    })?;
+    let fill = match &args[2] {
+        DataValue::Str(f) => {
+            let mut chars = f.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => bail!("third argument of '{}' must be a single character", name),
+            }
+        }
+        _ => bail!(ArgTypeError::new(name, 2, "strings")),
+    };
+    let len = s.chars().count() as u64;
+    if len >= width {
+        return Ok(DataValue::Str(s.clone()));
     }
+    let n_fill = (width - len) as usize;
+    let padded_len = n_fill
+        .checked_mul(fill.len_utf8())
+        .and_then(|l| l.checked_add(s.len()));
+    ensure!(
+        matches!(padded_len, Some(l) if l <= MAX_BUILT_STR_LEN),
+        "result of '{}' would be larger than {} bytes",
+        name,
+        MAX_BUILT_STR_LEN
+    );
+    let padding = fill.to_string().repeat(n_fill);
+    Ok(DataValue::from(if left {
+        padding + s as &str
+    } else {
+        s.to_string() + padding.as_str()
+    }))
 }
 
-define_op!(OP_STARTS_WITH, 2, false);
+define_op!(OP_STARTS_WITH, 2, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_starts_with(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Str(s) => s,
-        _ => bail!("'starts_with' requires strings"),
+        _ => bail!(ArgTypeError::new("starts_with", 0, "strings")),
     };
     let b = match &args[1] {
         DataValue::Str(s) => s,
-        _ => bail!("'starts_with' requires strings"),
+        _ => bail!(ArgTypeError::new("starts_with", 1, "strings")),
     };
     Ok(DataValue::from(a.starts_with(b as &str)))
 }
 
-define_op!(OP_ENDS_WITH, 2, false);
+define_op!(OP_ENDS_WITH, 2, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_ends_with(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Str(s) => s,
-        _ => bail!("'ends_with' requires strings"),
+        _ => bail!(ArgTypeError::new("ends_with", 0, "strings")),
     };
     let b = match &args[1] {
         DataValue::Str(s) => s,
-        _ => bail!("'ends_with' requires strings"),
+        _ => bail!(ArgTypeError::new("ends_with", 1, "strings")),
     };
     Ok(DataValue::from(a.ends_with(b as &str)))
 }
@@ -814,11 +1424,11 @@ pub(crate) fn op_regex(args: &[DataValue]) -> Result<DataValue> {
                 miette!("The string cannot be interpreted as regex: {}", err)
             })?))
         }
-        _ => bail!("'regex' requires strings"),
+        _ => bail!(ArgTypeError::new("regex", 0, "strings")),
     })
 }
 
-define_op!(OP_REGEX_MATCHES, 2, false);
+define_op!(OP_REGEX_MATCHES, 2, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_regex_matches(args: &[DataValue]) -> Result<DataValue> {
     match (&args[0], &args[1]) {
         (DataValue::Str(s), DataValue::Regex(r)) => Ok(DataValue::from(r.0.is_match(s))),
@@ -826,7 +1436,7 @@ pub(crate) fn op_regex_matches(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_REGEX_REPLACE, 3, false);
+define_op!(OP_REGEX_REPLACE, 3, false, returns: ReturnType::Always(ValueType::String));
 pub(crate) fn op_regex_replace(args: &[DataValue]) -> Result<DataValue> {
     match (&args[0], &args[1], &args[2]) {
         (DataValue::Str(s), DataValue::Regex(r), DataValue::Str(rp)) => {
@@ -836,7 +1446,7 @@ pub(crate) fn op_regex_replace(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_REGEX_REPLACE_ALL, 3, false);
+define_op!(OP_REGEX_REPLACE_ALL, 3, false, returns: ReturnType::Always(ValueType::String));
 pub(crate) fn op_regex_replace_all(args: &[DataValue]) -> Result<DataValue> {
     match (&args[0], &args[1], &args[2]) {
         (DataValue::Str(s), DataValue::Regex(r), DataValue::Str(rp)) => {
@@ -846,7 +1456,7 @@ pub(crate) fn op_regex_replace_all(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_REGEX_EXTRACT, 2, false);
+define_op!(OP_REGEX_EXTRACT, 2, false, returns: ReturnType::Always(ValueType::List));
 pub(crate) fn op_regex_extract(args: &[DataValue]) -> Result<DataValue> {
     match (&args[0], &args[1]) {
         (DataValue::Str(s), DataValue::Regex(r)) => {
@@ -871,12 +1481,17 @@ pub(crate) fn op_regex_extract_first(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_IS_NULL, 1, false);
+define_op!(OP_IS_NULL, 1, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_is_null(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(matches!(args[0], DataValue::Null)))
 }
 
-define_op!(OP_IS_INT, 1, false);
+define_op!(OP_IS_NOT_NULL, 1, false, returns: ReturnType::Always(ValueType::Bool));
+pub(crate) fn op_is_not_null(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(!matches!(args[0], DataValue::Null)))
+}
+
+define_op!(OP_IS_INT, 1, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_is_int(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(matches!(
         args[0],
@@ -884,7 +1499,7 @@ pub(crate) fn op_is_int(args: &[DataValue]) -> Result<DataValue> {
     )))
 }
 
-define_op!(OP_IS_FLOAT, 1, false);
+define_op!(OP_IS_FLOAT, 1, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_is_float(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(matches!(
         args[0],
@@ -892,7 +1507,7 @@ pub(crate) fn op_is_float(args: &[DataValue]) -> Result<DataValue> {
     )))
 }
 
-define_op!(OP_IS_NUM, 1, false);
+define_op!(OP_IS_NUM, 1, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_is_num(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(matches!(
         args[0],
@@ -900,7 +1515,7 @@ pub(crate) fn op_is_num(args: &[DataValue]) -> Result<DataValue> {
     )))
 }
 
-define_op!(OP_IS_FINITE, 1, false);
+define_op!(OP_IS_FINITE, 1, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_is_finite(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match &args[0] {
         DataValue::Num(Num::Int(_)) => true,
@@ -909,7 +1524,7 @@ pub(crate) fn op_is_finite(args: &[DataValue]) -> Result<DataValue> {
     }))
 }
 
-define_op!(OP_IS_INFINITE, 1, false);
+define_op!(OP_IS_INFINITE, 1, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_is_infinite(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match &args[0] {
         DataValue::Num(Num::Float(f)) => f.is_infinite(),
@@ -917,7 +1532,7 @@ pub(crate) fn op_is_infinite(args: &[DataValue]) -> Result<DataValue> {
     }))
 }
 
-define_op!(OP_IS_NAN, 1, false);
+define_op!(OP_IS_NAN, 1, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_is_nan(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match &args[0] {
         DataValue::Num(Num::Float(f)) => f.is_nan(),
@@ -925,12 +1540,12 @@ pub(crate) fn op_is_nan(args: &[DataValue]) -> Result<DataValue> {
     }))
 }
 
-define_op!(OP_IS_STRING, 1, false);
+define_op!(OP_IS_STRING, 1, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_is_string(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(matches!(args[0], DataValue::Str(_))))
 }
 
-define_op!(OP_IS_LIST, 1, false);
+define_op!(OP_IS_LIST, 1, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_is_list(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(matches!(
         args[0],
@@ -938,7 +1553,84 @@ pub(crate) fn op_is_list(args: &[DataValue]) -> Result<DataValue> {
     )))
 }
 
-define_op!(OP_APPEND, 2, false);
+define_op!(OP_TYPEOF, 1, false, returns: ReturnType::Always(ValueType::String));
+pub(crate) fn op_typeof(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(match &args[0] {
+        DataValue::Null | DataValue::Bot => "null",
+        DataValue::Bool(_) => "bool",
+        DataValue::Num(Num::Int(_)) => "int",
+        DataValue::Num(Num::Float(_)) => "float",
+        DataValue::Str(_) => "string",
+        DataValue::Bytes(_) => "bytes",
+        DataValue::Uuid(_) => "uuid",
+        DataValue::Regex(_) => "regex",
+        DataValue::List(_) | DataValue::Set(_) => "list",
+        DataValue::Validity(_) => "validity",
+        DataValue::Map(_) => "map",
+    }))
+}
+
+define_op!(OP_HASH, 1, false, returns: ReturnType::Always(ValueType::Int));
+/// 64-bit FNV-1a over the memcomparable encoding of the value, the same bytes that store it in a key,
+/// so the result does not depend on the run or the platform. Numbers are encoded as floats first,
+/// so that values equal under `==`, such as `1` and `1.0`, hash alike.
+pub(crate) fn op_hash(args: &[DataValue]) -> Result<DataValue> {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+    fn normalize(v: &DataValue) -> DataValue {
+        match v {
+            // `+ 0.0` turns `-0.0` into `0.0`
+            DataValue::Num(n) => DataValue::from(n.get_float() + 0.0),
+            DataValue::List(l) => DataValue::List(l.iter().map(normalize).collect()),
+            v => v.clone(),
+        }
+    }
+
+    let mut encoded = vec![];
+    encoded.encode_datavalue(&normalize(&args[0]));
+    let hash = encoded.iter().fold(FNV_OFFSET_BASIS, |h, b| {
+        (h ^ *b as u64).wrapping_mul(FNV_PRIME)
+    });
+    Ok(DataValue::from(hash as i64))
+}
+
+/// Limit on the length of lists that ops generate, so that a small query cannot exhaust memory
+const MAX_BUILT_LIST_LEN: i128 = 1 << 24;
+
+define_op!(OP_RANGE, 2, true, returns: ReturnType::Always(ValueType::List));
+// half-open, like ranges in Rust and Python: `range(0, 3)` is `[0, 1, 2]`
+pub(crate) fn op_range(args: &[DataValue]) -> Result<DataValue> {
+    ensure!(args.len() <= 3, "'range' takes at most 3 arguments");
+    let mut bounds = [0, 0, 1];
+    for (i, (arg, bound)) in args.iter().zip(bounds.iter_mut()).enumerate() {
+        *bound = match arg {
+            DataValue::Num(Num::Int(n)) => *n,
+            _ => bail!(ArgTypeError::new("range", i, "integers")),
+        };
+    }
+    let [start, stop, step] = bounds;
+    ensure!(step != 0, "step of 'range' must not be zero");
+    let (start, stop, step) = (start as i128, stop as i128, step as i128);
+    let len = if step > 0 {
+        (stop - start + step - 1) / step
+    } else {
+        (start - stop - step - 1) / -step
+    }
+    .max(0);
+    ensure!(
+        len <= MAX_BUILT_LIST_LEN,
+        "result of 'range' would be longer than {} elements",
+        MAX_BUILT_LIST_LEN
+    );
+    Ok(DataValue::List(
+        (0..len)
+            .map(|i| DataValue::from((start + i * step) as i64))
+            .collect(),
+    ))
+}
+
+define_op!(OP_APPEND, 2, false, returns: ReturnType::Always(ValueType::List));
 pub(crate) fn op_append(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
         DataValue::List(l) => {
@@ -955,7 +1647,7 @@ pub(crate) fn op_append(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_PREPEND, 2, false);
+define_op!(OP_PREPEND, 2, false, returns: ReturnType::Always(ValueType::List));
 pub(crate) fn op_prepend(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
         DataValue::List(pl) => {
@@ -972,23 +1664,41 @@ pub(crate) fn op_prepend(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_IS_BYTES, 1, false);
+define_op!(OP_IS_BYTES, 1, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_is_bytes(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(matches!(args[0], DataValue::Bytes(_))))
 }
 
-define_op!(OP_LENGTH, 1, false);
+define_op!(OP_LENGTH, 1, false, returns: ReturnType::Always(ValueType::Int));
 pub(crate) fn op_length(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match &args[0] {
         DataValue::Set(s) => s.len() as i64,
         DataValue::List(l) => l.len() as i64,
         DataValue::Str(s) => s.chars().count() as i64,
         DataValue::Bytes(b) => b.len() as i64,
-        _ => bail!("'length' requires lists"),
+        DataValue::Map(m) => m.len() as i64,
+        _ => bail!(ArgTypeError::new(
+            "length",
+            0,
+            "lists, sets, maps, strings or bytes"
+        )),
     }))
 }
 
-define_op!(OP_UNICODE_NORMALIZE, 2, false);
+define_op!(OP_HAS, 2, false, returns: ReturnType::Always(ValueType::Bool));
+// containment as in `is_in`, but with the container first
+pub(crate) fn op_has(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(match &args[0] {
+        // elements are compared as in `in`
+        DataValue::List(l) => l.iter().any(|el| values_eq(&args[1], el)),
+        DataValue::Set(s) => s.contains(&args[1]) || s.iter().any(|el| values_eq(&args[1], el)),
+        // keys are looked up as in `map_get`
+        DataValue::Map(m) => m.contains_key(&args[1]),
+        _ => bail!(ArgTypeError::new("has", 0, "lists, sets or maps")),
+    }))
+}
+
+define_op!(OP_UNICODE_NORMALIZE, 2, false, returns: ReturnType::Always(ValueType::String));
 pub(crate) fn op_unicode_normalize(args: &[DataValue]) -> Result<DataValue> {
     match (&args[0], &args[1]) {
         (DataValue::Str(s), DataValue::Str(n)) => Ok(DataValue::Str(match n as &str {
@@ -996,13 +1706,13 @@ pub(crate) fn op_unicode_normalize(args: &[DataValue]) -> Result<DataValue> {
             "nfd" => s.nfd().collect(),
             "nfkc" => s.nfkc().collect(),
             "nfkd" => s.nfkd().collect(),
-            u => bail!("unknown normalization {} for 'unicode_normalize'", u),
+            u => bail!("unknown normalization {:?} for 'unicode_normalize'", u),
         })),
         _ => bail!("'unicode_normalize' requires strings"),
     }
 }
 
-define_op!(OP_SORTED, 1, false);
+define_op!(OP_SORTED, 1, false, returns: ReturnType::Always(ValueType::List));
 pub(crate) fn op_sorted(args: &[DataValue]) -> Result<DataValue> {
     let mut arg = args[0]
         .get_slice()
@@ -1012,17 +1722,97 @@ pub(crate) fn op_sorted(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List(arg))
 }
 
-define_op!(OP_REVERSE, 1, false);
-pub(crate) fn op_reverse(args: &[DataValue]) -> Result<DataValue> {
+define_op!(OP_SORT_DESC, 1, false, returns: ReturnType::Always(ValueType::List));
+pub(crate) fn op_sort_desc(args: &[DataValue]) -> Result<DataValue> {
     let mut arg = args[0]
         .get_slice()
-        .ok_or_else(|| miette!("'reverse' requires lists"))?
+        .ok_or_else(|| miette!("'sort_desc' requires lists"))?
         .to_vec();
-    arg.reverse();
+    arg.sort_by(|a, b| b.cmp(a));
     Ok(DataValue::List(arg))
 }
 
-define_op!(OP_HAVERSINE, 4, false);
+define_op!(OP_DISTINCT, 1, false, returns: ReturnType::Always(ValueType::List));
+pub(crate) fn op_distinct(args: &[DataValue]) -> Result<DataValue> {
+    let l = args[0]
+        .get_slice()
+        .ok_or_else(|| miette!("'distinct' requires lists"))?;
+    let mut seen: BTreeSet<Vec<u8>> = BTreeSet::new();
+    let mut ret: Vec<DataValue> = vec![];
+    for el in l {
+        let first_seen = match eq_key(el) {
+            Some(key) => {
+                let mut encoded = vec![];
+                encoded.encode_datavalue(&key);
+                seen.insert(encoded)
+            }
+            // values containing NaN equal nothing, so they are always kept
+            None => true,
+        };
+        if first_seen {
+            ret.push(el.clone());
+        }
+    }
+    Ok(DataValue::List(ret))
+}
+
+// a value that is the same for all values equal under `values_eq`: integral floats become ints,
+// which also unifies `0.0` and `-0.0`, and there is no key for values containing NaN
+fn eq_key(v: &DataValue) -> Option<DataValue> {
+    Some(match v {
+        DataValue::Num(Num::Float(f)) if f.is_nan() => return None,
+        DataValue::Num(Num::Float(f))
+            if f.fract() == 0.0 && (i64::MIN as f64..i64::MAX as f64).contains(f) =>
+        {
+            DataValue::from(*f as i64)
+        }
+        DataValue::List(l) => DataValue::List(l.iter().map(eq_key).collect::<Option<_>>()?),
+        v => v.clone(),
+    })
+}
+
+define_op!(OP_LIST_MAP, 2, false, returns: ReturnType::Always(ValueType::List));
+pub(crate) fn op_list_map(args: &[DataValue]) -> Result<DataValue> {
+    let l = args[0]
+        .get_slice()
+        .ok_or_else(|| miette!("first argument of 'list_map' must be a list"))?;
+    let name = args[1]
+        .get_str()
+        .ok_or_else(|| miette!("second argument of 'list_map' must be a function name"))?;
+    let op = get_op(name).ok_or_else(|| miette!("'list_map': function {:?} not found", name))?;
+    ensure!(
+        op.min_arity == 1 || (op.vararg && op.min_arity == 0),
+        "'list_map' requires a unary function, but '{}' is not",
+        name
+    );
+    // `list_map` itself is folded and shared like any pure function
+    ensure!(
+        !op.has_side_effect() && op.is_deterministic(),
+        "'list_map' cannot apply '{}', which has side effects or varying results",
+        name
+    );
+    let ret: Vec<_> = l
+        .iter()
+        .map(|el| (op.inner)(std::slice::from_ref(el)))
+        .try_collect()?;
+    Ok(DataValue::List(ret))
+}
+
+define_op!(OP_REVERSE, 1, false, returns: ReturnType::SameAsFirst);
+pub(crate) fn op_reverse(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::List(l) => {
+            let mut l = l.clone();
+            l.reverse();
+            Ok(DataValue::List(l))
+        }
+        // reverses unicode scalar values, so combining marks end up before their base
+        DataValue::Str(s) => Ok(DataValue::Str(s.chars().rev().collect())),
+        _ => bail!("'reverse' requires lists or strings"),
+    }
+}
+
+define_op!(OP_HAVERSINE, 4, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_haversine(args: &[DataValue]) -> Result<DataValue> {
     let miette = || miette!("'haversine' requires numbers");
     let lat1 = args[0].get_float().ok_or_else(miette)?;
@@ -1037,7 +1827,7 @@ pub(crate) fn op_haversine(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(ret))
 }
 
-define_op!(OP_HAVERSINE_DEG_INPUT, 4, false);
+define_op!(OP_HAVERSINE_DEG_INPUT, 4, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_haversine_deg_input(args: &[DataValue]) -> Result<DataValue> {
     let miette = || miette!("'haversine_deg_input' requires numbers");
     let lat1 = args[0].get_float().ok_or_else(miette)? * f64::PI() / 180.;
@@ -1052,7 +1842,7 @@ pub(crate) fn op_haversine_deg_input(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(ret))
 }
 
-define_op!(OP_DEG_TO_RAD, 1, false);
+define_op!(OP_DEG_TO_RAD, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_deg_to_rad(args: &[DataValue]) -> Result<DataValue> {
     let x = args[0]
         .get_float()
@@ -1060,7 +1850,7 @@ pub(crate) fn op_deg_to_rad(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(x * f64::PI() / 180.))
 }
 
-define_op!(OP_RAD_TO_DEG, 1, false);
+define_op!(OP_RAD_TO_DEG, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_rad_to_deg(args: &[DataValue]) -> Result<DataValue> {
     let x = args[0]
         .get_float()
@@ -1088,7 +1878,41 @@ pub(crate) fn op_last(args: &[DataValue]) -> Result<DataValue> {
         .unwrap_or(DataValue::Null))
 }
 
-define_op!(OP_CHUNKS, 2, false);
+define_op!(OP_REST, 1, false, returns: ReturnType::Always(ValueType::List));
+pub(crate) fn op_rest(args: &[DataValue]) -> Result<DataValue> {
+    let l = args[0]
+        .get_slice()
+        .ok_or_else(|| miette!("'rest' requires lists"))?;
+    Ok(DataValue::List(l.iter().skip(1).cloned().collect_vec()))
+}
+
+define_op!(OP_TAKE, 2, false, returns: ReturnType::Always(ValueType::List));
+// `n` is clamped to the length of the list, but must not be negative
+pub(crate) fn op_take(args: &[DataValue]) -> Result<DataValue> {
+    let (l, n) = list_and_count(args, "take")?;
+    Ok(DataValue::List(l[..n.min(l.len())].to_vec()))
+}
+
+define_op!(OP_DROP, 2, false, returns: ReturnType::Always(ValueType::List));
+pub(crate) fn op_drop(args: &[DataValue]) -> Result<DataValue> {
+    let (l, n) = list_and_count(args, "drop")?;
+    Ok(DataValue::List(l[n.min(l.len())..].to_vec()))
+}
+
+fn list_and_count<'a>(args: &'a [DataValue], name: &str) -> Result<(&'a [DataValue], usize)> {
+    let l = args[0]
+        .get_slice()
+        .ok_or_else(|| miette!("first argument of '{}' must be a list", name))?;
+    let n = args[1].get_non_neg_int().ok_or_else(|| {
+        miette!(
+            "second argument of '{}' must be a non-negative integer",
+            name
+        )
+    })?;
+    Ok((l, usize::try_from(n).unwrap_or(usize::MAX)))
+}
+
+define_op!(OP_CHUNKS, 2, false, returns: ReturnType::Always(ValueType::List));
 pub(crate) fn op_chunks(args: &[DataValue]) -> Result<DataValue> {
     let arg = args[0]
         .get_slice()
@@ -1104,7 +1928,7 @@ pub(crate) fn op_chunks(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List(res))
 }
 
-define_op!(OP_CHUNKS_EXACT, 2, false);
+define_op!(OP_CHUNKS_EXACT, 2, false, returns: ReturnType::Always(ValueType::List));
 pub(crate) fn op_chunks_exact(args: &[DataValue]) -> Result<DataValue> {
     let arg = args[0]
         .get_slice()
@@ -1120,7 +1944,7 @@ pub(crate) fn op_chunks_exact(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List(res))
 }
 
-define_op!(OP_WINDOWS, 2, false);
+define_op!(OP_WINDOWS, 2, false, returns: ReturnType::Always(ValueType::List));
 pub(crate) fn op_windows(args: &[DataValue]) -> Result<DataValue> {
     let arg = args[0]
         .get_slice()
@@ -1136,6 +1960,40 @@ pub(crate) fn op_windows(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List(res))
 }
 
+define_op!(OP_ZIP, 2, false, returns: ReturnType::Always(ValueType::List));
+// the result is as long as the shorter list
+pub(crate) fn op_zip(args: &[DataValue]) -> Result<DataValue> {
+    let l = args[0]
+        .get_slice()
+        .ok_or_else(|| miette!("first argument of 'zip' must be a list"))?;
+    let r = args[1]
+        .get_slice()
+        .ok_or_else(|| miette!("second argument of 'zip' must be a list"))?;
+    let res = l
+        .iter()
+        .zip(r.iter())
+        .map(|(a, b)| DataValue::List(vec![a.clone(), b.clone()]))
+        .collect_vec();
+    Ok(DataValue::List(res))
+}
+
+define_op!(OP_FLATTEN, 1, false, returns: ReturnType::Always(ValueType::List));
+// one level only, and every element must itself be a list, so that the result is predictable
+pub(crate) fn op_flatten(args: &[DataValue]) -> Result<DataValue> {
+    let l = args[0]
+        .get_slice()
+        .ok_or_else(|| miette!("argument of 'flatten' must be a list"))?;
+    let mut ret = vec![];
+    for el in l {
+        match el {
+            DataValue::List(inner) => ret.extend_from_slice(inner),
+            DataValue::Set(inner) => ret.extend(inner.iter().cloned()),
+            _ => bail!("'flatten' requires a list of lists"),
+        }
+    }
+    Ok(DataValue::List(ret))
+}
+
 fn get_index(mut i: i64, total: usize) -> Result<usize> {
     if i < 0 {
         i += total as i64;
@@ -1156,10 +2014,10 @@ define_op!(OP_GET, 2, false);
 pub(crate) fn op_get(args: &[DataValue]) -> Result<DataValue> {
     let l = args[0]
         .get_slice()
-        .ok_or_else(|| miette!("first argument to 'get' mut be a list"))?;
+        .ok_or_else(|| miette!("first argument to 'get' must be a list"))?;
     let n = args[1]
         .get_int()
-        .ok_or_else(|| miette!("second argument to 'get' mut be an integer"))?;
+        .ok_or_else(|| miette!("second argument to 'get' must be an integer"))?;
     let idx = get_index(n, l.len())?;
     Ok(l[idx].clone())
 }
@@ -1168,10 +2026,10 @@ define_op!(OP_MAYBE_GET, 2, false);
 pub(crate) fn op_maybe_get(args: &[DataValue]) -> Result<DataValue> {
     let l = args[0]
         .get_slice()
-        .ok_or_else(|| miette!("first argument to 'maybe_get' mut be a list"))?;
+        .ok_or_else(|| miette!("first argument to 'maybe_get' must be a list"))?;
     let n = args[1]
         .get_int()
-        .ok_or_else(|| miette!("second argument to 'maybe_get' mut be an integer"))?;
+        .ok_or_else(|| miette!("second argument to 'maybe_get' must be an integer"))?;
     if let Ok(idx) = get_index(n, l.len()) {
         Ok(l[idx].clone())
     } else {
@@ -1179,7 +2037,7 @@ pub(crate) fn op_maybe_get(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_SLICE, 3, false);
+define_op!(OP_SLICE, 3, false, returns: ReturnType::Always(ValueType::List));
 pub(crate) fn op_slice(args: &[DataValue]) -> Result<DataValue> {
     let l = args[0]
         .get_slice()
@@ -1195,7 +2053,34 @@ pub(crate) fn op_slice(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List(l[m..n].to_vec()))
 }
 
-define_op!(OP_CHARS, 1, false);
+define_op!(OP_SUBLIST, 3, false, returns: ReturnType::Always(ValueType::List));
+pub(crate) fn op_sublist(args: &[DataValue]) -> Result<DataValue> {
+    let l = args[0]
+        .get_slice()
+        .ok_or_else(|| miette!("first argument to 'sublist' must be a list"))?;
+    // unlike 'slice', bounds are clamped to the list, and null means unbounded
+    let clamp = |bound: &DataValue, default: usize| -> Result<usize> {
+        Ok(match bound {
+            DataValue::Null => default,
+            v => {
+                let i = v
+                    .get_int()
+                    .ok_or_else(|| miette!("bounds of 'sublist' must be integers or null"))?;
+                let i = if i < 0 { i + l.len() as i64 } else { i };
+                i.clamp(0, l.len() as i64) as usize
+            }
+        })
+    };
+    let m = clamp(&args[1], 0)?;
+    let n = clamp(&args[2], l.len())?;
+    Ok(DataValue::List(if m < n {
+        l[m..n].to_vec()
+    } else {
+        vec![]
+    }))
+}
+
+define_op!(OP_CHARS, 1, false, returns: ReturnType::Always(ValueType::List));
 pub(crate) fn op_chars(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List(
         args[0]
@@ -1211,7 +2096,7 @@ pub(crate) fn op_chars(args: &[DataValue]) -> Result<DataValue> {
     ))
 }
 
-define_op!(OP_FROM_SUBSTRINGS, 1, false);
+define_op!(OP_FROM_SUBSTRINGS, 1, false, returns: ReturnType::Always(ValueType::String));
 pub(crate) fn op_from_substrings(args: &[DataValue]) -> Result<DataValue> {
     let mut ret = String::new();
     match &args[0] {
@@ -1238,7 +2123,30 @@ pub(crate) fn op_from_substrings(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(ret))
 }
 
-define_op!(OP_ENCODE_BASE64, 1, false);
+define_op!(OP_FROM_CHARS, 1, false, returns: ReturnType::Always(ValueType::String));
+// the inverse of `chars`: unlike `from_substrings`, every element must be a single char
+pub(crate) fn op_from_chars(args: &[DataValue]) -> Result<DataValue> {
+    let l = args[0]
+        .get_slice()
+        .ok_or_else(|| ArgTypeError::new("from_chars", 0, "lists"))?;
+    let mut ret = String::new();
+    for el in l {
+        let c = el.get_str().and_then(|s| {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(c),
+                _ => None,
+            }
+        });
+        match c {
+            Some(c) => ret.push(c),
+            None => bail!("'from_chars' requires a list of single-character strings"),
+        }
+    }
+    Ok(DataValue::from(ret))
+}
+
+define_op!(OP_ENCODE_BASE64, 1, false, returns: ReturnType::Always(ValueType::String));
 pub(crate) fn op_encode_base64(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
         DataValue::Bytes(b) => {
@@ -1249,7 +2157,7 @@ pub(crate) fn op_encode_base64(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_DECODE_BASE64, 1, false);
+define_op!(OP_DECODE_BASE64, 1, false, returns: ReturnType::Always(ValueType::Bytes));
 pub(crate) fn op_decode_base64(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
         DataValue::Str(s) => {
@@ -1258,11 +2166,44 @@ pub(crate) fn op_decode_base64(args: &[DataValue]) -> Result<DataValue> {
                 .map_err(|_| miette!("Data is not properly encoded"))?;
             Ok(DataValue::Bytes(b))
         }
-        _ => bail!("'decode_base64' requires strings"),
+        _ => bail!(ArgTypeError::new("decode_base64", 0, "strings")),
     }
 }
 
-define_op!(OP_TO_BOOL, 1, false);
+define_op!(OP_TO_HEX, 1, false, returns: ReturnType::Always(ValueType::String));
+pub(crate) fn op_to_hex(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Bytes(b) => {
+            let s: String = b.iter().map(|byte| format!("{byte:02x}")).collect();
+            Ok(DataValue::from(s))
+        }
+        _ => bail!("'to_hex' requires bytes"),
+    }
+}
+
+define_op!(OP_FROM_HEX, 1, false, returns: ReturnType::Always(ValueType::Bytes));
+pub(crate) fn op_from_hex(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Str(s) => {
+            ensure!(
+                s.len() % 2 == 0,
+                "Hex data must have an even number of digits"
+            );
+            let b = (0..s.len())
+                .step_by(2)
+                .map(|i| {
+                    s.get(i..i + 2)
+                        .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                        .ok_or_else(|| miette!("Data is not properly encoded"))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(DataValue::Bytes(b))
+        }
+        _ => bail!(ArgTypeError::new("from_hex", 0, "strings")),
+    }
+}
+
+define_op!(OP_TO_BOOL, 1, false, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_to_bool(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match &args[0] {
         DataValue::Null => false,
@@ -1274,12 +2215,13 @@ pub(crate) fn op_to_bool(args: &[DataValue]) -> Result<DataValue> {
         DataValue::Regex(r) => !r.0.as_str().is_empty(),
         DataValue::List(l) => !l.is_empty(),
         DataValue::Set(s) => !s.is_empty(),
+        DataValue::Map(m) => !m.is_empty(),
         DataValue::Validity(vld) => vld.is_assert.0,
         DataValue::Bot => false,
     }))
 }
 
-define_op!(OP_TO_UNITY, 1, false);
+define_op!(OP_TO_UNITY, 1, false, returns: ReturnType::Always(ValueType::Int));
 pub(crate) fn op_to_unity(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match &args[0] {
         DataValue::Null => 0,
@@ -1291,12 +2233,13 @@ pub(crate) fn op_to_unity(args: &[DataValue]) -> Result<DataValue> {
         DataValue::Regex(r) => i64::from(!r.0.as_str().is_empty()),
         DataValue::List(l) => i64::from(!l.is_empty()),
         DataValue::Set(s) => i64::from(!s.is_empty()),
+        DataValue::Map(m) => i64::from(!m.is_empty()),
         DataValue::Validity(vld) => i64::from(vld.is_assert.0),
         DataValue::Bot => 0,
     }))
 }
 
-define_op!(OP_TO_INT, 1, false);
+define_op!(OP_TO_INT, 1, false, returns: ReturnType::Always(ValueType::Int));
 pub(crate) fn op_to_int(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(n) => match n.get_int() {
@@ -1311,7 +2254,7 @@ pub(crate) fn op_to_int(args: &[DataValue]) -> Result<DataValue> {
         DataValue::Str(t) => {
             let s = t as &str;
             i64::from_str(s)
-                .map_err(|_| miette!("The string cannot be interpreted as int"))?
+                .map_err(|_| miette!("The string {:?} cannot be interpreted as int", s))?
                 .into()
         }
         DataValue::Validity(vld) => DataValue::Num(Num::Int(vld.timestamp.0 .0)),
@@ -1319,7 +2262,27 @@ pub(crate) fn op_to_int(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
-define_op!(OP_TO_FLOAT, 1, false);
+define_op!(OP_PARSE_INT_RADIX, 2, false, returns: ReturnType::Always(ValueType::Int));
+// digits past 9 are letters of either case, and a leading sign is allowed
+pub(crate) fn op_parse_int_radix(args: &[DataValue]) -> Result<DataValue> {
+    let s = args[0]
+        .get_str()
+        .ok_or_else(|| ArgTypeError::new("parse_int_radix", 0, "strings"))?;
+    let radix = match args[1].get_int() {
+        Some(r @ 2..=36) => r as u32,
+        _ => bail!("radix of 'parse_int_radix' must be an integer between 2 and 36"),
+    };
+    let i = i64::from_str_radix(s, radix).map_err(|_| {
+        miette!(
+            "The string {:?} cannot be interpreted as int in radix {}",
+            s,
+            radix
+        )
+    })?;
+    Ok(DataValue::from(i))
+}
+
+define_op!(OP_TO_FLOAT, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_to_float(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(n) => n.get_float().into(),
@@ -1332,17 +2295,19 @@ pub(crate) fn op_to_float(args: &[DataValue]) -> Result<DataValue> {
             "INF" => f64::INFINITY.into(),
             "NEG_INF" => f64::NEG_INFINITY.into(),
             s => f64::from_str(s)
-                .map_err(|_| miette!("The string cannot be interpreted as float"))?
+                .map_err(|_| miette!("The string {:?} cannot be interpreted as float", s))?
                 .into(),
         },
         v => bail!("'to_float' does not recognize {:?}", v),
     })
 }
 
-define_op!(OP_TO_STRING, 1, false);
+define_op!(OP_TO_STRING, 1, false, returns: ReturnType::Always(ValueType::String));
 pub(crate) fn op_to_string(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Str(s) => DataValue::Str(s.clone()),
+        DataValue::Uuid(UuidWrapper(id)) => DataValue::from(id.to_string()),
+        DataValue::Num(Num::Float(f)) => DataValue::from(float_to_string(*f)),
         v => {
             let jv = JsonValue::from(v.clone());
             let s = jv.to_string();
@@ -1351,12 +2316,77 @@ pub(crate) fn op_to_string(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
-define_op!(OP_RAND_FLOAT, 0, false);
+/// The shortest representation that reads back as the same float, always with a decimal
+/// point or an exponent, e.g. `1.0`, `0.001`, `1e-5` and `1e16`. Scientific notation is
+/// used below `1e-4` and from `1e16` up. Non-finite values are `NaN`, `inf` and `-inf`.
+fn float_to_string(f: f64) -> String {
+    format!("{f:?}")
+}
+
+// the smallest subnormal float is exact with this many digits after the point, more are all zeros
+const MAX_FLOAT_PRECISION: usize = 1074;
+
+define_op!(OP_FORMAT_FLOAT, 2, false, returns: ReturnType::Always(ValueType::String));
+pub(crate) fn op_format_float(args: &[DataValue]) -> Result<DataValue> {
+    let f = args[0]
+        .get_float()
+        .ok_or_else(|| ArgTypeError::new("format_float", 0, "numbers"))?;
+    let precision = args[1].get_non_neg_int().ok_or_else(|| {
+        miette!("second argument of 'format_float' must be a non-negative integer")
+    })? as usize;
+    ensure!(
+        precision <= MAX_FLOAT_PRECISION,
+        "precision of 'format_float' cannot be larger than {MAX_FLOAT_PRECISION}"
+    );
+    Ok(DataValue::from(format!("{f:.precision$}")))
+}
+
+define_op!(OP_FORMAT, 1, true, returns: ReturnType::Always(ValueType::String));
+pub(crate) fn op_format(args: &[DataValue]) -> Result<DataValue> {
+    let template = args[0]
+        .get_str()
+        .ok_or_else(|| miette!("first argument of 'format' must be a string"))?;
+    let mut fills = args[1..].iter();
+    let mut ret = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                ret.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                let fill = fills.next().ok_or_else(|| {
+                    miette!("'format' has more placeholders than arguments to fill them")
+                })?;
+                match op_to_string(std::slice::from_ref(fill))? {
+                    DataValue::Str(s) => ret.push_str(&s),
+                    _ => unreachable!(),
+                }
+            }
+            ('{' | '}', _) => bail!(
+                "unmatched '{}' in 'format' template, use '{}{}' for a literal",
+                c,
+                c,
+                c
+            ),
+            _ => ret.push(c),
+        }
+    }
+    ensure!(
+        fills.next().is_none(),
+        "'format' has more arguments than placeholders"
+    );
+    Ok(DataValue::from(ret))
+}
+
+define_op!(OP_RAND_FLOAT, 0, false, impure, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_rand_float(_args: &[DataValue]) -> Result<DataValue> {
     Ok(thread_rng().gen::<f64>().into())
 }
 
-define_op!(OP_RAND_BERNOULLI, 1, false);
+define_op!(OP_RAND_BERNOULLI, 1, false, impure, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_rand_bernoulli(args: &[DataValue]) -> Result<DataValue> {
     let prob = match &args[0] {
         DataValue::Num(n) => {
@@ -1372,7 +2402,7 @@ pub(crate) fn op_rand_bernoulli(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(thread_rng().gen_bool(prob)))
 }
 
-define_op!(OP_RAND_INT, 2, false);
+define_op!(OP_RAND_INT, 2, false, impure, returns: ReturnType::Always(ValueType::Int));
 pub(crate) fn op_rand_int(args: &[DataValue]) -> Result<DataValue> {
     let lower = &args[0]
         .get_int()
@@ -1380,10 +2410,15 @@ pub(crate) fn op_rand_int(args: &[DataValue]) -> Result<DataValue> {
     let upper = &args[1]
         .get_int()
         .ok_or_else(|| miette!("'rand_int' requires integers"))?;
-    Ok(thread_rng().gen_range(*lower..=*upper).into())
+    // the upper bound is exclusive, so the range must not be empty
+    ensure!(
+        lower < upper,
+        "'rand_int' requires the lower bound to be less than the upper bound"
+    );
+    Ok(thread_rng().gen_range(*lower..*upper).into())
 }
 
-define_op!(OP_RAND_CHOOSE, 1, false);
+define_op!(OP_RAND_CHOOSE, 1, false, impure);
 pub(crate) fn op_rand_choose(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
         DataValue::List(l) => Ok(l
@@ -1401,7 +2436,7 @@ pub(crate) fn op_rand_choose(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_ASSERT, 1, true);
+define_op!(OP_ASSERT, 1, true, returns: ReturnType::Always(ValueType::Bool));
 pub(crate) fn op_assert(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
         DataValue::Bool(true) => Ok(DataValue::from(true)),
@@ -1409,7 +2444,14 @@ pub(crate) fn op_assert(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_UNION, 1, true);
+define_op!(
+    OP_UNION,
+    1,
+    true,
+    commutative: true,
+    associative: true,
+    returns: ReturnType::Always(ValueType::List),
+);
 pub(crate) fn op_union(args: &[DataValue]) -> Result<DataValue> {
     let mut ret = BTreeSet::new();
     for arg in args {
@@ -1430,7 +2472,7 @@ pub(crate) fn op_union(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List(ret.into_iter().collect()))
 }
 
-define_op!(OP_DIFFERENCE, 2, true);
+define_op!(OP_DIFFERENCE, 2, true, returns: ReturnType::Always(ValueType::List));
 pub(crate) fn op_difference(args: &[DataValue]) -> Result<DataValue> {
     let mut start: BTreeSet<_> = match &args[0] {
         DataValue::List(l) => l.iter().cloned().collect(),
@@ -1455,7 +2497,14 @@ pub(crate) fn op_difference(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List(start.into_iter().collect()))
 }
 
-define_op!(OP_INTERSECTION, 1, true);
+define_op!(
+    OP_INTERSECTION,
+    1,
+    true,
+    commutative: true,
+    associative: true,
+    returns: ReturnType::Always(ValueType::List),
+);
 pub(crate) fn op_intersection(args: &[DataValue]) -> Result<DataValue> {
     let mut start: BTreeSet<_> = match &args[0] {
         DataValue::List(l) => l.iter().cloned().collect(),
@@ -1475,7 +2524,65 @@ pub(crate) fn op_intersection(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List(start.into_iter().collect()))
 }
 
-define_op!(OP_TO_UUID, 1, false);
+define_op!(OP_MAP, 0, true, returns: ReturnType::Always(ValueType::Map));
+// keys and values alternate, and a later value for the same key replaces an earlier one
+pub(crate) fn op_map(args: &[DataValue]) -> Result<DataValue> {
+    let pairs = args.chunks_exact(2);
+    ensure!(
+        pairs.remainder().is_empty(),
+        "'map' requires a value to follow each key"
+    );
+    Ok(DataValue::Map(
+        pairs.map(|kv| (kv[0].clone(), kv[1].clone())).collect(),
+    ))
+}
+
+fn get_map<'a>(
+    name: &'static str,
+    arg: &'a DataValue,
+) -> Result<&'a BTreeMap<DataValue, DataValue>> {
+    match arg {
+        DataValue::Map(m) => Ok(m),
+        _ => bail!(ArgTypeError::new(name, 0, "maps")),
+    }
+}
+
+define_op!(OP_MAP_GET, 2, false);
+// keys are compared by their ordering, so `1` and `1.0` are different keys
+pub(crate) fn op_map_get(args: &[DataValue]) -> Result<DataValue> {
+    Ok(get_map("map_get", &args[0])?
+        .get(&args[1])
+        .cloned()
+        .unwrap_or(DataValue::Null))
+}
+
+define_op!(OP_MAP_KEYS, 1, false, returns: ReturnType::Always(ValueType::List));
+pub(crate) fn op_map_keys(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::List(
+        get_map("map_keys", &args[0])?.keys().cloned().collect(),
+    ))
+}
+
+define_op!(OP_MAP_VALUES, 1, false, returns: ReturnType::Always(ValueType::List));
+// in the order of their keys
+pub(crate) fn op_map_values(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::List(
+        get_map("map_values", &args[0])?.values().cloned().collect(),
+    ))
+}
+
+define_op!(OP_MAP_PUT, 3, false, returns: ReturnType::Always(ValueType::Map));
+pub(crate) fn op_map_put(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::Map(
+        get_map("map_put", &args[0])?
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .chain([(args[1].clone(), args[2].clone())])
+            .collect(),
+    ))
+}
+
+define_op!(OP_TO_UUID, 1, false, returns: ReturnType::Always(ValueType::Uuid));
 pub(crate) fn op_to_uuid(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
         d @ DataValue::Uuid(_u) => Ok(d.clone()),
@@ -1487,7 +2594,7 @@ pub(crate) fn op_to_uuid(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_NOW, 0, false);
+define_op!(OP_NOW, 0, false, impure, returns: ReturnType::Always(ValueType::Float));
 #[cfg(target_arch = "wasm32")]
 pub(crate) fn op_now(_args: &[DataValue]) -> Result<DataValue> {
     let d: f64 = Date::now() / 1000.;
@@ -1520,7 +2627,7 @@ pub(crate) const TERMINAL_VALIDITY: Validity = Validity {
     is_assert: Reverse(false),
 };
 
-define_op!(OP_FORMAT_TIMESTAMP, 1, true);
+define_op!(OP_FORMAT_TIMESTAMP, 1, true, returns: ReturnType::Always(ValueType::String));
 pub(crate) fn op_format_timestamp(args: &[DataValue]) -> Result<DataValue> {
     let dt = {
         let millis = match &args[0] {
@@ -1542,7 +2649,7 @@ pub(crate) fn op_format_timestamp(args: &[DataValue]) -> Result<DataValue> {
                 miette!("'format_timestamp' timezone specification requires a string")
             })?;
             let tz = chrono_tz::Tz::from_str(tz_s)
-                .map_err(|_| miette!("bad timezone specification: {}", tz_s))?;
+                .map_err(|_| miette!("bad timezone specification: {:?}", tz_s))?;
             let dt_tz = dt.with_timezone(&tz);
             let s = SmartString::from(dt_tz.to_rfc3339());
             Ok(DataValue::Str(s))
@@ -1554,26 +2661,26 @@ pub(crate) fn op_format_timestamp(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_PARSE_TIMESTAMP, 1, false);
+define_op!(OP_PARSE_TIMESTAMP, 1, false, returns: ReturnType::Always(ValueType::Float));
 pub(crate) fn op_parse_timestamp(args: &[DataValue]) -> Result<DataValue> {
     let s = args[0]
         .get_str()
         .ok_or_else(|| miette!("'parse_timestamp' expects a string"))?;
-    let dt = DateTime::parse_from_rfc3339(s).map_err(|_| miette!("bad datetime: {}", s))?;
-    let st: SystemTime = dt.into();
+    let dt = DateTime::parse_from_rfc3339(s).map_err(|_| miette!("bad datetime: {:?}", s))?;
+    // not via `SystemTime`, which cannot represent times before the epoch
     Ok(DataValue::from(
-        st.duration_since(UNIX_EPOCH).unwrap().as_secs_f64(),
+        dt.timestamp() as f64 + dt.timestamp_subsec_micros() as f64 / 1_000_000.,
     ))
 }
 
 pub(crate) fn str2vld(s: &str) -> Result<ValidityTs> {
-    let dt = DateTime::parse_from_rfc3339(s).map_err(|_| miette!("bad datetime: {}", s))?;
+    let dt = DateTime::parse_from_rfc3339(s).map_err(|_| miette!("bad datetime: {:?}", s))?;
     let st: SystemTime = dt.into();
     let microseconds = st.duration_since(UNIX_EPOCH).unwrap().as_micros();
     Ok(ValidityTs(Reverse(microseconds as i64)))
 }
 
-define_op!(OP_RAND_UUID_V1, 0, false);
+define_op!(OP_RAND_UUID_V1, 0, false, impure, returns: ReturnType::Always(ValueType::Uuid));
 pub(crate) fn op_rand_uuid_v1(_args: &[DataValue]) -> Result<DataValue> {
     let mut rng = rand::thread_rng();
     let uuid_ctx = uuid::v1::Context::new(rng.gen());
@@ -1596,7 +2703,7 @@ pub(crate) fn op_rand_uuid_v1(_args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::uuid(id))
 }
 
-define_op!(OP_RAND_UUID_V4, 0, false);
+define_op!(OP_RAND_UUID_V4, 0, false, impure, returns: ReturnType::Always(ValueType::Uuid));
 pub(crate) fn op_rand_uuid_v4(_args: &[DataValue]) -> Result<DataValue> {
     let id = uuid::Uuid::new_v4();
     Ok(DataValue::uuid(id))
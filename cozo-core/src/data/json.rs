@@ -100,6 +100,12 @@ impl From<DataValue> for JsonValue {
             DataValue::Validity(v) => {
                 json!([v.timestamp.0, v.is_assert])
             }
+            // keys need not be strings, so write pairs, the shape JSON objects are read into
+            DataValue::Map(m) => JsonValue::Array(
+                m.into_iter()
+                    .map(|(k, v)| json!([JsonValue::from(k), JsonValue::from(v)]))
+                    .collect(),
+            ),
         }
     }
 }
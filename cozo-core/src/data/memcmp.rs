@@ -28,6 +28,7 @@ const REGEX_TAG: u8 = 0x09;
 const LIST_TAG: u8 = 0x0A;
 const SET_TAG: u8 = 0x0B;
 const VLD_TAG: u8 = 0x0C;
+const MAP_TAG: u8 = 0x0D;
 const BOT_TAG: u8 = 0xFF;
 
 const IS_FLOAT: u8 = 0b00010000;
@@ -88,6 +89,14 @@ pub(crate) trait MemCmpEncoder: Write {
                 self.write_u64::<BigEndian>(ts_flipped).unwrap();
                 self.write_u8(!vld.is_assert.0 as u8).unwrap();
             }
+            DataValue::Map(m) => {
+                self.write_u8(MAP_TAG).unwrap();
+                for (k, v) in m {
+                    self.encode_datavalue(k);
+                    self.encode_datavalue(v);
+                }
+                self.write_u8(INIT_TAG).unwrap()
+            }
             DataValue::Bot => self.write_u8(BOT_TAG).unwrap(),
         }
     }
@@ -294,6 +303,20 @@ impl DataValue {
                     rest,
                 )
             }
+            MAP_TAG => {
+                let mut collected = vec![];
+                let mut remaining = remaining;
+                while remaining[0] != INIT_TAG {
+                    let (key, next_chunk) = DataValue::decode_from_key(remaining);
+                    let (val, next_chunk) = DataValue::decode_from_key(next_chunk);
+                    remaining = next_chunk;
+                    collected.push((key, val));
+                }
+                (
+                    DataValue::Map(collected.into_iter().collect()),
+                    &remaining[1..],
+                )
+            }
             BOT_TAG => (DataValue::Bot, remaining),
             _ => unreachable!("{:?}", bs),
         }
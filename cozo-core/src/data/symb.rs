@@ -72,6 +72,14 @@ impl Debug for Symbol {
     }
 }
 
+/// A symbol that does not come from any source, such as a name bound by
+/// [`Expr::evaluate`](crate::Expr::evaluate)
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Self::new(name, SourceSpan::default())
+    }
+}
+
 impl Symbol {
     pub(crate) fn new(name: impl Into<SmartString<LazyCompact>>, span: SourceSpan) -> Self {
         Self {
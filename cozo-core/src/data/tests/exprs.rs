@@ -6,7 +6,34 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use crate::{new_cozo_mem, DataValue};
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use itertools::Itertools;
+use pest::Parser;
+
+use crate::data::expr::{eval_bytecode, get_op, Bytecode, Expr, Op};
+use crate::data::functions::{
+    OP_ADD, OP_AND, OP_COALESCE, OP_CONCAT, OP_DIV, OP_EQ, OP_LIST, OP_MUL, OP_OR, OP_SUB,
+};
+use crate::parse::expr::{
+    build_expr, build_nested_expr, expr2bytecode, expr2str, parse_string, NestingTooDeepError,
+    MAX_EXPR_DEPTH,
+};
+use crate::parse::{CozoScriptParser, Rule, SourceSpan};
+use crate::{
+    new_cozo_mem, parse_expr_structured, register_op, DataValue, ParseDiagnostic, SourcePosition,
+    Symbol, ValueType,
+};
+
+fn parse_expr(src: &str) -> Expr {
+    let pair = CozoScriptParser::parse(Rule::expr, src)
+        .unwrap()
+        .next()
+        .unwrap();
+    build_expr(pair, &Default::default()).unwrap()
+}
 
 #[test]
 fn expression_eval() {
@@ -32,3 +59,1477 @@ fn expression_eval() {
         .unwrap();
     assert_eq!(res.rows[0][0].get_bool().unwrap(), true);
 }
+
+#[test]
+fn infix_span_covers_operator() {
+    assert_eq!(parse_expr("1 + 2").span(), SourceSpan(0, 5));
+    assert_eq!(parse_expr("a  ++  b").span(), SourceSpan(0, 8));
+    let expr = parse_expr("x * (y - 1)");
+    assert_eq!(expr.span(), SourceSpan(0, 10));
+}
+
+#[test]
+fn short_circuit_and_or() {
+    let db = new_cozo_mem().unwrap();
+
+    let res = db
+        .run_script(
+            r#"
+    ?[a, b] := a = false && assert(false), b = true || assert(false)
+    "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(false));
+    assert_eq!(res.rows[0][1], DataValue::from(true));
+
+    let res = db
+        .run_script(
+            r#"
+    ?[x] := x in [1, 2, 3], x > 2 || assert(x < 3)
+    "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.rows.len(), 3);
+
+    assert!(db
+        .run_script("?[a] := a = true && assert(false)", Default::default())
+        .is_err());
+    assert!(db
+        .run_script("?[a] := a = 1 && true", Default::default())
+        .is_err());
+    assert!(db
+        .run_script("?[a] := a = false || 1", Default::default())
+        .is_err());
+}
+
+#[test]
+fn and_or_chains_are_flattened() {
+    let arity = |src: &str, expected_op: &Op| match parse_expr(src) {
+        Expr::Apply { op, args, .. } => {
+            assert_eq!(op, expected_op, "{src}");
+            args.len()
+        }
+        _ => panic!("{src}"),
+    };
+    assert_eq!(arity("a && b && c && d", &OP_AND), 4);
+    assert_eq!(arity("a && b && (c && d)", &OP_AND), 4);
+    assert_eq!(arity("a || b || c", &OP_OR), 3);
+    // different operators are not merged
+    assert_eq!(arity("a && b || c && d", &OP_OR), 2);
+    assert_eq!(arity("a ~ b ~ c", &OP_COALESCE), 2);
+
+    // evaluation stops at the first decisive argument
+    for src in [
+        "true && false && assert(false) && true",
+        "false || true || assert(false) || false",
+    ] {
+        let expr = parse_expr(src);
+        let expected = DataValue::from(src.starts_with("false"));
+        assert_eq!(expr.eval(&vec![]).unwrap(), expected, "{src}");
+        let mut bytecode = vec![];
+        expr2bytecode(&expr, &mut bytecode);
+        assert_eq!(
+            eval_bytecode(&bytecode, &vec![], &mut vec![]).unwrap(),
+            expected,
+            "{src}"
+        );
+    }
+}
+
+#[test]
+fn algebraic_properties_of_ops() {
+    for op in [&OP_ADD, &OP_MUL, &OP_AND, &OP_OR] {
+        assert!(op.is_commutative(), "{}", op.name);
+        assert!(op.is_associative(), "{}", op.name);
+    }
+    assert!(!OP_SUB.is_commutative());
+    assert!(!OP_SUB.is_associative());
+    assert!(!OP_DIV.is_associative());
+    assert!(OP_EQ.is_commutative());
+    assert!(!OP_EQ.is_associative());
+    assert!(!OP_CONCAT.is_commutative());
+    assert!(OP_CONCAT.is_associative());
+
+    let arity = |src: &str| match parse_expr(src) {
+        Expr::Apply { args, .. } => args.len(),
+        _ => panic!("{src}"),
+    };
+    assert_eq!(arity("a + b + c"), 3);
+    assert_eq!(arity("a * b * c * d"), 4);
+    assert_eq!(arity("a ++ b ++ (c ++ d)"), 4);
+    assert_eq!(arity("a - b - c"), 2);
+    // regrouping float additions would change the rounding
+    assert_eq!(arity("a + (b + c)"), 2);
+    assert_eq!(
+        parse_expr("0.1 + (0.2 + 0.3)").eval(&vec![]).unwrap(),
+        DataValue::from(0.1 + (0.2 + 0.3))
+    );
+    assert_eq!(expr2str(&parse_expr("a + b + c")), "a + b + c");
+}
+
+#[test]
+fn three_valued_and_or() {
+    let db = new_cozo_mem().unwrap();
+    let vals = ["true", "false", "null"];
+    for l in vals {
+        for r in vals {
+            let res = db
+                .run_script(
+                    &format!("?[a, b] := l = {l}, r = {r}, a = l && r, b = l || r"),
+                    Default::default(),
+                )
+                .unwrap();
+            let expected_and = if l == "false" || r == "false" {
+                DataValue::from(false)
+            } else if l == "null" || r == "null" {
+                DataValue::Null
+            } else {
+                DataValue::from(true)
+            };
+            let expected_or = if l == "true" || r == "true" {
+                DataValue::from(true)
+            } else if l == "null" || r == "null" {
+                DataValue::Null
+            } else {
+                DataValue::from(false)
+            };
+            assert_eq!(res.rows[0][0], expected_and, "{l} && {r}");
+            assert_eq!(res.rows[0][1], expected_or, "{l} || {r}");
+        }
+    }
+}
+
+#[test]
+fn sql_style_not_equal() {
+    assert_eq!(parse_expr("1 <> 2"), parse_expr("1 != 2"));
+    assert_eq!(parse_expr("a <> b || c"), parse_expr("a != b || c"));
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script("?[a, b] := a = 1 <> 2, b = 1 <> 1", Default::default())
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(true));
+    assert_eq!(res.rows[0][1], DataValue::from(false));
+}
+
+#[test]
+fn comments_in_expressions() {
+    let plain = parse_expr("1 + x * [2, 3]").to_string();
+    for src in [
+        "1 + // one\n x * [2, 3]",
+        "1 + # one\n x * [2, 3]",
+        "1 /* one */ + x * [2, /* two */ 3]",
+        "1 + x /* outer /* nested */ still outer */ * [2, 3]",
+    ] {
+        assert_eq!(parse_expr(src).to_string(), plain, "{src}");
+    }
+
+    let db = new_cozo_mem().unwrap();
+    assert!(db
+        .run_script("?[a] := a = 1 + /* unclosed /* */ 2", Default::default())
+        .is_err());
+    let res = db
+        .run_script(
+            r#"
+    ?[a] := a = 1 + // comment
+                2 /* another */ * 3
+    "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(7));
+}
+
+#[test]
+fn subscripts() {
+    let eval = |src: &str| parse_expr(src).eval_to_const().unwrap();
+    let list = |xs: &[i64]| DataValue::List(xs.iter().map(|x| DataValue::from(*x)).collect());
+
+    match parse_expr("xs[1:3]") {
+        Expr::Apply { op, args, span } => {
+            assert_eq!(op.name, "OP_SUBLIST");
+            assert_eq!(args.len(), 3);
+            assert_eq!(span, SourceSpan(0, 7));
+        }
+        _ => panic!("subscript should desugar to an application"),
+    }
+    match parse_expr("xs[i]") {
+        Expr::Apply { op, args, .. } => {
+            assert_eq!(op.name, "OP_MAYBE_GET");
+            assert_eq!(args.len(), 2);
+        }
+        _ => panic!("subscript should desugar to an application"),
+    }
+
+    assert_eq!(eval("[1, 2, 3, 4][1]"), DataValue::from(2));
+    assert_eq!(eval("[1, 2, 3, 4][-1]"), DataValue::from(4));
+    assert_eq!(eval("[1, 2, 3, 4][4]"), DataValue::Null);
+    assert_eq!(eval("[1, 2, 3, 4][1:3]"), list(&[2, 3]));
+    assert_eq!(eval("[1, 2, 3, 4][:2]"), list(&[1, 2]));
+    assert_eq!(eval("[1, 2, 3, 4][1:]"), list(&[2, 3, 4]));
+    assert_eq!(eval("[1, 2, 3, 4][:]"), list(&[1, 2, 3, 4]));
+    assert_eq!(eval("[1, 2, 3, 4][-3:-1]"), list(&[2, 3]));
+    assert_eq!(eval("[1, 2, 3, 4][-10:10]"), list(&[1, 2, 3, 4]));
+    assert_eq!(eval("[1, 2, 3, 4][3:1]"), list(&[]));
+    assert_eq!(eval("[[1, 2], [3]][0][1]"), DataValue::from(2));
+    assert_eq!(eval("-[1, 2][1] + 1"), DataValue::from(-1));
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            r#"?[a, b] := xs = [1, 2, 3, 4, 5], a = xs[-2:], b = xs[1 + 1]"#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.rows[0], vec![list(&[4, 5]), DataValue::from(3)]);
+}
+
+#[test]
+fn list_spread() {
+    let eval = |src: &str| parse_expr(src).eval_to_const();
+    let list = |xs: &[i64]| DataValue::List(xs.iter().map(|x| DataValue::from(*x)).collect());
+
+    match parse_expr("[1, 2]") {
+        Expr::Apply { op, .. } => assert_eq!(op.name, "OP_LIST"),
+        _ => panic!("list literal should be an application"),
+    }
+    match parse_expr("[1, ..xs, 2]") {
+        Expr::Apply { op, args, .. } => {
+            assert_eq!(op.name, "OP_CONCAT");
+            assert_eq!(args.len(), 3);
+        }
+        _ => panic!("list literal should be an application"),
+    }
+
+    assert_eq!(eval("[1, ..[2, 3], 4]").unwrap(), list(&[1, 2, 3, 4]));
+    assert_eq!(eval("[..[1, 2]]").unwrap(), list(&[1, 2]));
+    assert_eq!(eval("[1, ..[]]").unwrap(), list(&[1]));
+    assert_eq!(eval("[..[1], ..[2], 3,]").unwrap(), list(&[1, 2, 3]));
+    assert_eq!(
+        eval("[..[[1]]]").unwrap(),
+        DataValue::List(vec![list(&[1])])
+    );
+    assert!(eval(r#"[1, .."ab"]"#).is_err());
+    assert!(eval(r#"[.."ab"]"#).is_err());
+    assert!(eval("[..1]").is_err());
+    assert!(eval(r#"[.."a", .."b"]"#).is_err());
+    assert_eq!(expr2str(&parse_expr("[..xs, 1]")), "xs ++ [1]");
+    assert_eq!(expr2str(&parse_expr("[..xs, ..ys]")), "[] ++ xs ++ ys");
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            r#"?[a] := xs = [2, 3], a = [1, ..xs, 4] == [1, 2, 3, 4]"#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(true));
+}
+
+thread_local! {
+    static TICKS: Cell<i64> = Cell::new(0);
+}
+
+fn op_tick(_args: &[DataValue]) -> miette::Result<DataValue> {
+    Ok(DataValue::from(TICKS.with(|t| t.replace(t.get() + 1))))
+}
+
+const OP_TICK: Op = Op {
+    name: "OP_TICK",
+    min_arity: 0,
+    vararg: true,
+    inner: op_tick,
+    ..Op::PLAIN
+};
+
+// arguments are ignored, but distinguish calls that would otherwise be shared as pure
+fn tick(args: &[i64]) -> Expr {
+    Expr::Apply {
+        op: &OP_TICK,
+        args: args
+            .iter()
+            .map(|i| Expr::Const {
+                val: DataValue::from(*i),
+                span: Default::default(),
+            })
+            .collect(),
+        span: Default::default(),
+    }
+}
+
+#[test]
+fn arguments_evaluate_left_to_right() {
+    let expr = Expr::Apply {
+        op: &OP_LIST,
+        args: [
+            tick(&[1]),
+            Expr::Apply {
+                op: &OP_ADD,
+                args: [tick(&[2]), tick(&[3])].into(),
+                span: Default::default(),
+            },
+            tick(&[4]),
+        ]
+        .into(),
+        span: Default::default(),
+    };
+    let expected = |base: i64| {
+        DataValue::List(vec![
+            DataValue::from(base),
+            DataValue::from(2 * base + 3),
+            DataValue::from(base + 3),
+        ])
+    };
+
+    let base = TICKS.with(|t| t.get());
+    assert_eq!(expr.eval(&vec![]).unwrap(), expected(base));
+
+    let mut bytecode = vec![];
+    expr2bytecode(&expr, &mut bytecode);
+    let base = TICKS.with(|t| t.get());
+    assert_eq!(
+        eval_bytecode(&bytecode, &vec![], &mut vec![]).unwrap(),
+        expected(base)
+    );
+}
+
+#[test]
+fn walk_visits_in_pre_order() {
+    let expr = parse_expr("a + concat(b, c)");
+    let mut visited = vec![];
+    expr.walk(&mut |e| {
+        visited.push(match e {
+            Expr::Binding { var, .. } => var.name.to_string(),
+            Expr::Const { val, .. } => val.to_string(),
+            Expr::Apply { op, .. } => op.name.to_string(),
+            Expr::Cond { .. } => "COND".to_string(),
+        })
+    });
+    assert_eq!(visited, ["OP_ADD", "a", "OP_CONCAT", "b", "c"]);
+
+    let expr = parse_expr("if(x > 1, y, 2)");
+    let mut visited = vec![];
+    expr.walk(&mut |e| {
+        if let Some(v) = e.get_binding() {
+            visited.push(v.name.to_string())
+        }
+    });
+    assert_eq!(visited, ["x", "y"]);
+}
+
+#[test]
+fn free_variables() {
+    let names = |src: &str| {
+        parse_expr(src)
+            .bindings()
+            .into_iter()
+            .map(|s| s.name.to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names("x + 1"), ["x"]);
+    assert_eq!(names("concat(a, [a + b])"), ["a", "b"]);
+    assert_eq!(names("if(p, q, max(r, 1, s))"), ["p", "q", "r", "s"]);
+    assert!(names("1 + 2").is_empty());
+}
+
+#[test]
+fn print_round_trip() {
+    for (src, printed) in [
+        ("a + b * c", "a + b * c"),
+        ("(a + b) * c", "(a + b) * c"),
+        ("a - (b - c)", "a - (b - c)"),
+        ("(a - b) - c", "a - b - c"),
+        ("2 ^ 3 ^ 4", "2 ^ 3 ^ 4"),
+        ("(2 ^ 3) ^ 4", "(2 ^ 3) ^ 4"),
+        ("-(a + 1) * -b", "-(a + 1) * -b"),
+        ("!(x && y) || z", "!(x && y) || z"),
+        ("a && b && (c && d)", "a && b && c && d"),
+        ("(a || b) && c || d", "(a || b) && c || d"),
+        ("a ~ b ^ 2", "a ~ b ^ 2"),
+        ("(a ~ b) == null", "a ~ b == null"),
+        ("1 + 2 ++ x", "1 + 2 ++ x"),
+        ("a ++ (b ++ c)", "a ++ b ++ c"),
+        ("(a ++ b) * 2", "(a ++ b) * 2"),
+        ("x in [1, 2.0, 'q']", "x in [1, 2.0, 'q']"),
+        ("[x, 1e100, 0.5]", "[x, 1e100, 0.5]"),
+        (
+            r#"concat('a"b\'', '\\\n\t\u0001')"#,
+            r#"'a"b\'' ++ '\\\n\t\u0001'"#,
+        ),
+        (
+            "xs[1:] ++ xs[-1]",
+            "sublist(xs, 1, null) ++ maybe_get(xs, -1)",
+        ),
+        ("if(a > 1, b, c)", "cond(a > 1, b, true, c)"),
+        ("regex_matches(s, '[a-z]+')", "regex_matches(s, '[a-z]+')"),
+        ("hex\"00ff\"", "hex\"00ff\""),
+        ("{'a': x + 1, 2: []}", "{'a': x + 1, 2: []}"),
+    ] {
+        let expr = parse_expr(src);
+        let out = expr2str(&expr);
+        assert_eq!(out, printed, "printing {src}");
+        let reparsed = parse_expr(&out);
+        assert_eq!(
+            format!("{reparsed:?}"),
+            format!("{expr:?}"),
+            "reparsing {out}"
+        );
+        assert_eq!(expr2str(&reparsed), out);
+    }
+
+    // trees the parser never builds from infix operators print as function calls
+    let bin = |op: &'static Op, l: Expr, r: Expr| Expr::Apply {
+        op,
+        args: [l, r].into(),
+        span: Default::default(),
+    };
+    let var = |name: &str| parse_expr(name);
+    let zero = || Expr::Const {
+        val: DataValue::from(0),
+        span: Default::default(),
+    };
+    for (expr, printed) in [
+        (
+            bin(&OP_CONCAT, var("a"), bin(&OP_CONCAT, var("b"), var("c"))),
+            "concat(a, b ++ c)",
+        ),
+        (
+            bin(&OP_AND, bin(&OP_AND, var("a"), var("b")), var("c")),
+            "and(a && b, c)",
+        ),
+        (
+            bin(&OP_ADD, bin(&OP_ADD, var("a"), var("b")), var("c")),
+            "add(a + b, c)",
+        ),
+        (
+            bin(&OP_ADD, var("a"), bin(&OP_ADD, var("b"), var("c"))),
+            "a + (b + c)",
+        ),
+        (
+            bin(&OP_ADD, parse_expr("length(l)"), zero()),
+            "add(length(l), 0)",
+        ),
+    ] {
+        let out = expr2str(&expr);
+        assert_eq!(out, printed);
+        assert_eq!(
+            format!("{:?}", parse_expr(&out)),
+            format!("{expr:?}"),
+            "{out}"
+        );
+    }
+}
+
+#[test]
+fn side_effects_are_not_folded() {
+    let mut expr = parse_expr("rand() + rand()");
+    expr.partial_eval().unwrap();
+    match &expr {
+        Expr::Apply { op, args, .. } => {
+            assert_eq!(op.name, "OP_ADD");
+            assert!(args.iter().all(|arg| arg.get_const().is_none()));
+        }
+        _ => panic!("expression should not have been folded"),
+    }
+    let a = expr.eval(&vec![]).unwrap();
+    let b = expr.eval(&vec![]).unwrap();
+    assert_ne!(a, b);
+
+    let mut expr = parse_expr("rand_int(0, 1000000) * (2 + 3)");
+    expr.partial_eval().unwrap();
+    assert_eq!(expr2str(&expr), "rand_int(0, 1000000) * 5");
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script("?[a, b] := a = rand(), b = rand()", Default::default())
+        .unwrap();
+    assert_ne!(res.rows[0][0], res.rows[0][1]);
+}
+
+#[test]
+fn common_subexpressions_evaluate_once() {
+    let run = |expr: &Expr| {
+        let bytecode = expr.compile();
+        let base = TICKS.with(|t| t.get());
+        let res = eval_bytecode(&bytecode, &vec![], &mut vec![]).unwrap();
+        (res, TICKS.with(|t| t.get()) - base, base)
+    };
+
+    // tick() + tick() * tick()
+    let expr = Expr::Apply {
+        op: &OP_ADD,
+        args: [
+            tick(&[]),
+            Expr::Apply {
+                op: &OP_MUL,
+                args: [tick(&[]), tick(&[])].into(),
+                span: Default::default(),
+            },
+        ]
+        .into(),
+        span: Default::default(),
+    };
+    let (res, evaluations, base) = run(&expr);
+    assert_eq!(evaluations, 1);
+    assert_eq!(res, DataValue::from(base + base * base));
+
+    // the first occurrence is in a branch never taken, so the second computes the value
+    let expr = Expr::Apply {
+        op: &OP_LIST,
+        args: [
+            Expr::Cond {
+                clauses: vec![
+                    (
+                        Expr::Const {
+                            val: DataValue::from(false),
+                            span: Default::default(),
+                        },
+                        tick(&[]),
+                    ),
+                    (
+                        Expr::Const {
+                            val: DataValue::from(true),
+                            span: Default::default(),
+                        },
+                        Expr::Const {
+                            val: DataValue::from(-1),
+                            span: Default::default(),
+                        },
+                    ),
+                ],
+                span: Default::default(),
+            },
+            tick(&[]),
+            tick(&[]),
+        ]
+        .into(),
+        span: Default::default(),
+    };
+    let (res, evaluations, base) = run(&expr);
+    assert_eq!(evaluations, 1);
+    assert_eq!(
+        res,
+        DataValue::List(vec![
+            DataValue::from(-1),
+            DataValue::from(base),
+            DataValue::from(base)
+        ])
+    );
+
+    // side effects are never shared; every occurrence of a shared expression may fill its slot
+    let bytecode = parse_expr("rand() + rand() - concat(a, b) == concat(a, b)").compile();
+    let memoized = bytecode
+        .iter()
+        .filter_map(|c| match c {
+            Bytecode::Memoize { slot } => Some(*slot),
+            _ => None,
+        })
+        .collect_vec();
+    assert_eq!(memoized, [0, 0]);
+    let res = eval_bytecode(
+        &parse_expr("[rand(), rand()]").compile(),
+        &vec![],
+        &mut vec![],
+    )
+    .unwrap();
+    let res = res.get_slice().unwrap();
+    assert_ne!(res[0], res[1]);
+}
+
+// a plain `"..."` in scripts is a raw string, so the escapes of double-quoted strings can
+// only be reached through their own rule
+fn quoted(src: &str) -> miette::Result<DataValue> {
+    let pair = CozoScriptParser::parse(Rule::quoted_string, src)
+        .unwrap()
+        .next()
+        .unwrap();
+    parse_string(pair).map(DataValue::Str)
+}
+
+#[test]
+fn quoted_strings() {
+    let eval = |src: &str| parse_expr(src).eval_to_const().unwrap();
+    assert_eq!(
+        quoted(r#""say \"hi\"""#).unwrap(),
+        DataValue::from(r#"say "hi""#)
+    );
+    assert_eq!(eval(r#"'it\'s'"#), DataValue::from("it's"));
+    assert_eq!(quoted(r#""it's""#).unwrap(), DataValue::from("it's"));
+    assert_eq!(eval(r#"'say "hi"'"#), DataValue::from(r#"say "hi""#));
+    assert_eq!(
+        quoted(r#""\\ \/ \b \f \n \r \t é""#).unwrap(),
+        DataValue::from("\\ / \x08 \x0c \n \r \t é")
+    );
+    assert_eq!(
+        eval(r#"'\\ \/ \b \f \n \r \t é'"#),
+        DataValue::from("\\ / \x08 \x0c \n \r \t é")
+    );
+    assert!(quoted(r#""it\'s""#).is_err());
+
+    // backslashes in double-quoted strings of scripts are kept as written
+    assert_eq!(eval(r#""\d+ \n""#), DataValue::from(r"\d+ \n"));
+    assert_eq!(eval(r#""C:\tmp""#), DataValue::from(r"C:\tmp"));
+    assert_eq!(eval(r#"_"\d+ \n"_"#), DataValue::from(r"\d+ \n"));
+    assert_eq!(eval(r#"__"a "_" b"__"#), DataValue::from(r#"a "_" b"#));
+
+    let db = new_cozo_mem().unwrap();
+    assert!(db
+        .run_script(r#"?[a] := a = 'say \"hi\"'"#, Default::default())
+        .is_err());
+    let res = db
+        .run_script(
+            r#"?[a] := a = regex_matches("12", "\d+")"#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(true));
+}
+
+#[test]
+fn surrogate_pairs() {
+    let eval = |src: &str| parse_expr(src).eval_to_const();
+    assert_eq!(quoted(r#""\uD83D\uDE00""#).unwrap(), DataValue::from("😀"));
+    assert_eq!(
+        eval(r#"'a\ud83d\ude00b'"#).unwrap(),
+        DataValue::from("a😀b")
+    );
+    assert_eq!(quoted(r#""\u00e9""#).unwrap(), DataValue::from("é"));
+    assert!(quoted(r#""\uD83D""#).is_err());
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(r#"?[a] := a = '\uD83D\uDE00'"#, Default::default())
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from("😀"));
+    for src in [
+        r#"?[a] := a = '\uD83D'"#,
+        r#"?[a] := a = '\uD83Dx'"#,
+        r#"?[a] := a = '\uD83DA'"#,
+        r#"?[a] := a = '\uDE00\uD83D'"#,
+        r#"?[a] := a = '\uDE00'"#,
+    ] {
+        assert!(db.run_script(src, Default::default()).is_err(), "{src}");
+    }
+}
+
+#[test]
+fn byte_and_nul_escapes() {
+    let eval = |src: &str| parse_expr(src).eval_to_const().unwrap();
+    assert_eq!(quoted(r#""\x41""#).unwrap(), DataValue::from("A"));
+    assert_eq!(eval(r#"'\x41\x62c'"#), DataValue::from("Abc"));
+    assert_eq!(quoted(r#""\xe9""#).unwrap(), DataValue::from("é"));
+    assert_eq!(quoted(r#""\0""#).unwrap(), DataValue::from("\0"));
+    assert_eq!(eval(r#"length('\0')"#), DataValue::from(1));
+    assert!(quoted(r#""\xZZ""#).is_err());
+
+    let db = new_cozo_mem().unwrap();
+    for src in [
+        r#"?[a] := a = '\xZZ'"#,
+        r#"?[a] := a = '\x4'"#,
+        r#"?[a] := a = '\x+1'"#,
+        r#"?[a] := a = '\x'"#,
+        r#"?[a] := a = '\u12'"#,
+        r#"?[a] := a = '\q'"#,
+    ] {
+        let err = db.run_script(src, Default::default()).unwrap_err();
+        assert!(err.to_string().contains("invalid escape sequence"), "{src}");
+    }
+}
+
+#[test]
+fn operator_function_names() {
+    let eval = |src: &str| parse_expr(src).eval_to_const().unwrap();
+    for (call, infix) in [
+        ("add(7, 2)", "7 + 2"),
+        ("sub(7, 2)", "7 - 2"),
+        ("mul(7, 2)", "7 * 2"),
+        ("div(7, 2)", "7 / 2"),
+        ("mod(7, 2)", "7 % 2"),
+        ("pow(2, 3)", "2 ^ 3"),
+        ("neg(5)", "-5"),
+        ("minus(5)", "-5"),
+    ] {
+        assert_eq!(eval(call), eval(infix), "{call}");
+    }
+    assert_eq!(eval("neg(5)"), DataValue::from(-5));
+    assert_eq!(eval("pow(2, 3)"), DataValue::from(8.0));
+}
+
+#[test]
+fn argument_type_errors_point_at_the_argument() {
+    let expr = parse_expr(r#"1 + ln("x")"#);
+    let check = |err: miette::Report| {
+        assert!(err.to_string().contains("Evaluation"), "{err}");
+        let help = err.help().unwrap().to_string();
+        assert!(
+            help.contains("'ln' requires numbers for argument 1"),
+            "{help}"
+        );
+        let label = err.labels().unwrap().next().unwrap();
+        assert_eq!((label.offset(), label.len()), (7, 3));
+    };
+
+    check(expr.eval(&vec![]).unwrap_err());
+
+    let mut bytecode = vec![];
+    expr2bytecode(&expr, &mut bytecode);
+    check(eval_bytecode(&bytecode, &vec![], &mut vec![]).unwrap_err());
+}
+
+#[test]
+fn overflowing_int_literals() {
+    let build = |src: &str| {
+        let pair = CozoScriptParser::parse(Rule::expr, src)
+            .unwrap()
+            .next()
+            .unwrap();
+        build_expr(pair, &Default::default())
+    };
+    for src in [
+        "0xFFFFFFFFFFFFFFFFFF",
+        "0o7777777777777777777777",
+        "0b11111111111111111111111111111111111111111111111111111111111111111",
+    ] {
+        let err = build(src).unwrap_err();
+        assert_eq!(err.to_string(), "Cannot parse integer", "{src}");
+    }
+    assert_eq!(
+        build("0x7FFF_FFFF_FFFF_FFFF")
+            .unwrap()
+            .eval(&vec![])
+            .unwrap(),
+        DataValue::from(i64::MAX)
+    );
+}
+
+#[test]
+fn digit_separators_in_int_literals() {
+    let build = |src: &str| {
+        let pair = CozoScriptParser::parse(Rule::expr_with_term, src)
+            .unwrap()
+            .next()
+            .unwrap();
+        build_expr(pair.into_inner().next().unwrap(), &Default::default())
+    };
+    for (src, expected) in [
+        ("1_000", 1000),
+        ("0xFF_FF", 0xFFFF),
+        ("0o7_7", 0o77),
+        ("0b1_0", 2),
+    ] {
+        let val = build(src).unwrap().eval(&vec![]).unwrap();
+        assert_eq!(val, DataValue::from(expected), "{src}");
+    }
+    for src in ["0x_FF", "1__000", "1_000_", "0xFF_", "0b__1", "0o1__0"] {
+        let err = build(src).unwrap_err();
+        assert_eq!(err.to_string(), "Cannot parse integer", "{src}");
+    }
+}
+
+#[test]
+fn trailing_comma_in_list() {
+    let show = |src: &str| format!("{:?}", parse_expr(src));
+    assert_eq!(show("[1, 2, 3,]"), show("[1, 2, 3]"));
+    assert_eq!(show("[\n  1,\n  ..a,\n]"), show("[1, ..a]"));
+    assert_eq!(show("[[],]"), show("[[]]"));
+    assert!(CozoScriptParser::parse(Rule::list, "[,]").is_err());
+    assert!(CozoScriptParser::parse(Rule::list, "[1,,]").is_err());
+}
+
+#[test]
+fn map_literals() {
+    let eval = |src: &str| parse_expr(src).eval_to_const();
+
+    match parse_expr("{'a': 1}") {
+        Expr::Apply { op, args, .. } => {
+            assert_eq!(op.name, "OP_MAP");
+            assert_eq!(args.len(), 2);
+        }
+        _ => panic!("map literal should be an application"),
+    }
+    let m = eval("{'b': 2, 'a': 1 + 0,}").unwrap();
+    assert_eq!(
+        m,
+        DataValue::Map(BTreeMap::from([
+            (DataValue::from("a"), DataValue::from(1)),
+            (DataValue::from("b"), DataValue::from(2)),
+        ]))
+    );
+    assert_eq!(eval("{}").unwrap(), DataValue::Map(BTreeMap::new()));
+    assert_eq!(
+        eval("{1: 'x', 1: 'y'}").unwrap(),
+        DataValue::Map(BTreeMap::from([(DataValue::from(1), DataValue::from("y"))]))
+    );
+
+    // constant maps fold, and print back as literals
+    let mut folded = parse_expr("{'b': 2, 'a': 1 + 0}");
+    folded.partial_eval().unwrap();
+    assert_eq!(expr2str(&folded), "{'a': 1, 'b': 2}");
+    assert!(CozoScriptParser::parse(Rule::map, "{,}").is_err());
+    assert!(CozoScriptParser::parse(Rule::map, "{1}").is_err());
+}
+
+#[test]
+fn evaluate_with_named_bindings() {
+    let expr = parse_expr("x + 1");
+    let mut bindings = BTreeMap::new();
+    bindings.insert(Symbol::new("x", Default::default()), DataValue::from(2));
+    assert_eq!(expr.evaluate(&bindings).unwrap(), DataValue::from(3));
+    // extra bindings are ignored
+    bindings.insert(Symbol::new("y", Default::default()), DataValue::Null);
+    assert_eq!(expr.evaluate(&bindings).unwrap(), DataValue::from(3));
+
+    let err = parse_expr("x + z").evaluate(&bindings).unwrap_err();
+    assert_eq!(err.to_string(), "The variable 'z' is unbound");
+    let label = err.labels().unwrap().next().unwrap();
+    assert_eq!((label.offset(), label.len()), (4, 1));
+
+    assert_eq!(
+        parse_expr("concat('a', 'b')")
+            .evaluate(&BTreeMap::new())
+            .unwrap(),
+        DataValue::from("ab")
+    );
+}
+
+#[test]
+fn evaluate_through_public_api() {
+    let expr = parse_expr_structured("x * y").unwrap();
+    let bindings = BTreeMap::from([
+        (Symbol::from("x"), DataValue::from(6)),
+        (Symbol::from("y"), DataValue::from(7)),
+    ]);
+    assert_eq!(expr.evaluate(&bindings).unwrap(), DataValue::from(42));
+}
+
+#[test]
+fn concat_binds_looser_than_arithmetic() {
+    let top = |src: &str| match parse_expr(src) {
+        Expr::Apply { op, .. } => op.name,
+        _ => panic!("{src}"),
+    };
+    assert_eq!(top(r#"1 + 2 ++ "x""#), "OP_CONCAT");
+    assert_eq!(top(r#""x" ++ 1 * 2"#), "OP_CONCAT");
+    assert_eq!(top("a % b ++ c"), "OP_CONCAT");
+    assert_eq!(top("a ++ b == c"), "OP_EQ");
+    assert_eq!(top("a ++ b ~ c"), "OP_CONCAT");
+
+    let err = parse_expr(r#"1 + 2 ++ "x""#).eval(&vec![]).unwrap_err();
+    let help = err.help().unwrap().to_string();
+    assert_eq!(help, "'concat' requires strings or lists for argument 1");
+    let label = err.labels().unwrap().next().unwrap();
+    assert_eq!((label.offset(), label.len()), (0, 5));
+
+    let err = parse_expr(r#""x" ++ 1 + 2"#).eval(&vec![]).unwrap_err();
+    let help = err.help().unwrap().to_string();
+    assert_eq!(help, "'concat' requires strings for argument 2");
+    let label = err.labels().unwrap().next().unwrap();
+    assert_eq!((label.offset(), label.len()), (7, 5));
+}
+
+#[test]
+fn predicate_errors_summarize_large_values() {
+    let mut bindings = BTreeMap::new();
+    let huge = DataValue::List((0..100_000).map(DataValue::from).collect());
+    bindings.insert(Symbol::new("x", Default::default()), huge);
+    bindings.insert(
+        Symbol::new("s", Default::default()),
+        DataValue::from("ab".repeat(10_000)),
+    );
+
+    let err = parse_expr("x || true").evaluate(&bindings).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Found value <list of length 100000> where a boolean value is expected"
+    );
+    let err = parse_expr("if(s, 1, 2)").evaluate(&bindings).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Found value {:?}... where a boolean value is expected",
+            "ab".repeat(16)
+        )
+    );
+    let err = parse_expr("1 && true").evaluate(&bindings).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Found value 1 where a boolean value is expected"
+    );
+}
+
+#[test]
+fn percent_literals() {
+    let eval = |src: &str| parse_expr(src).eval(&vec![]).unwrap();
+    assert_eq!(eval("50%"), DataValue::from(0.5));
+    assert_eq!(eval("50% == 0.5"), DataValue::from(true));
+    assert_eq!(eval("12.5%"), DataValue::from(0.125));
+    assert_eq!(eval("1_000%"), DataValue::from(10.));
+    assert_eq!(eval("80 * 10%"), DataValue::from(8.));
+    assert_eq!(eval("(200 * 10%)"), DataValue::from(20.));
+    assert_eq!(eval("[25%, 75%]"), eval("[0.25, 0.75]"));
+    assert_eq!(eval("50% + 1"), DataValue::from(1.5));
+    // followed by an operand, `%` is still the remainder operator
+    assert_eq!(eval("10%3"), DataValue::from(1));
+    assert_eq!(eval("10 % 3"), DataValue::from(1));
+    assert_eq!(eval("10%-3"), DataValue::from(1));
+    assert_eq!(eval("-10 % -3"), DataValue::from(-1));
+    assert_eq!(expr2str(&parse_expr("10% !x")), "10 % !x");
+    assert_eq!(eval("10% (3)"), DataValue::from(1));
+
+    let mut bindings = BTreeMap::new();
+    bindings.insert(
+        Symbol::new("price", Default::default()),
+        DataValue::from(30),
+    );
+    assert_eq!(
+        parse_expr("price * 10%").evaluate(&bindings).unwrap(),
+        DataValue::from(3.)
+    );
+    assert_eq!(
+        parse_expr("10%price").evaluate(&bindings).unwrap(),
+        DataValue::from(10)
+    );
+}
+
+#[test]
+fn pow_is_right_associative() {
+    let expr = parse_expr("2 ^ 3 ^ 2");
+    match &expr {
+        Expr::Apply { op, args, .. } => {
+            assert_eq!(op.name, "OP_POW");
+            assert_eq!(format!("{:?}", args[0]), "2");
+            assert_eq!(format!("{:?}", args[1]), "pow(3, 2)");
+        }
+        _ => panic!(),
+    }
+    assert_eq!(expr.eval(&vec![]).unwrap(), DataValue::from(512.));
+    let mut bytecode = vec![];
+    expr2bytecode(&expr, &mut bytecode);
+    assert_eq!(
+        eval_bytecode(&bytecode, &vec![], &mut vec![]).unwrap(),
+        DataValue::from(512.)
+    );
+    assert_eq!(
+        parse_expr("(2 ^ 3) ^ 2").eval(&vec![]).unwrap(),
+        DataValue::from(64.)
+    );
+    // prefix minus binds tighter than `^`
+    assert_eq!(
+        parse_expr("-2 ^ 2").eval(&vec![]).unwrap(),
+        DataValue::from(4.)
+    );
+}
+
+#[test]
+fn structured_parse_errors() {
+    assert_eq!(
+        format!("{:?}", parse_expr_structured("a + 1").unwrap()),
+        "add(a, 1)"
+    );
+
+    let errs = parse_expr_structured("1 + 2e_").unwrap_err();
+    assert_eq!(
+        errs,
+        vec![ParseDiagnostic {
+            code: "parser::bad_float".to_string(),
+            span: (4, 3),
+            message: "Cannot parse float".to_string(),
+        }]
+    );
+
+    let errs = parse_expr_structured("1 + 2 )").unwrap_err();
+    assert_eq!(errs.len(), 1);
+    assert_eq!(errs[0].code, "parser::pest");
+    assert_eq!(errs[0].span, (6, 0));
+
+    let errs = parse_expr_structured("$x + 1").unwrap_err();
+    assert_eq!(errs[0].span, (0, 2));
+}
+
+#[test]
+fn independent_parse_errors_are_all_reported() {
+    let summary = |src: &str| {
+        parse_expr_structured(src)
+            .unwrap_err()
+            .into_iter()
+            .map(|d| (d.code, d.span))
+            .collect_vec()
+    };
+    assert_eq!(
+        summary("foo(1) + 2e_"),
+        [
+            ("parser::func_not_function".to_string(), (0, 3)),
+            ("parser::bad_float".to_string(), (9, 3)),
+        ]
+    );
+    assert_eq!(
+        summary("[1e_, bar(2e_), 3][0x8000000000000000]"),
+        [
+            ("parser::bad_float".to_string(), (1, 3)),
+            ("parser::func_not_function".to_string(), (6, 3)),
+            ("parser::bad_float".to_string(), (10, 3)),
+            ("parser::bad_pos_int".to_string(), (19, 18)),
+        ]
+    );
+    // a single error is reported as is
+    assert_eq!(
+        summary("1 + -2e_"),
+        [("parser::bad_float".to_string(), (5, 3))]
+    );
+
+    let db = new_cozo_mem().unwrap();
+    let err = db
+        .run_script("?[a] := a = foo(1) + 2e_", Default::default())
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Found 2 errors in expression");
+    assert_eq!(err.related().unwrap().count(), 2);
+}
+
+#[test]
+fn inferred_types() {
+    let known = [
+        ("1 + 2", ValueType::Int),
+        ("1 + 2.5", ValueType::Float),
+        ("a + b", ValueType::Number),
+        ("a / 2", ValueType::Float),
+        ("length(x)", ValueType::Int),
+        ("a == b", ValueType::Bool),
+        ("a > 1 && true", ValueType::Bool),
+        ("lowercase(s) ++ 'x'", ValueType::String),
+        ("[a, b]", ValueType::List),
+        ("if(a, 1, 2)", ValueType::Int),
+        ("max(1, 2)", ValueType::Int),
+        ("coalesce(1, a)", ValueType::Int),
+    ];
+    for (src, expected) in known {
+        assert_eq!(parse_expr(src).infer_type(), Some(expected), "{src}");
+    }
+    let unknown = [
+        "a",
+        "a && b",
+        "first(l)",
+        "if(a, 1, 'x')",
+        "if(a, 1)",
+        "coalesce(a, 1)",
+        "a ++ b",
+    ];
+    for src in unknown {
+        assert_eq!(parse_expr(src).infer_type(), None, "{src}");
+    }
+}
+
+#[test]
+fn nesting_depth_is_limited() {
+    let build = |src: &str, max_depth: usize| {
+        let pair = CozoScriptParser::parse(Rule::expr, src)
+            .unwrap()
+            .next()
+            .unwrap();
+        build_nested_expr(pair, &Default::default(), max_depth)
+    };
+    assert!(build("[[[1]]]", 4).is_ok());
+    let err = build("[[[1]]]", 3).unwrap_err();
+    let err = err.downcast_ref::<NestingTooDeepError>().unwrap();
+    assert_eq!(err.0, SourceSpan(3, 1));
+
+    let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+    assert!(build(&nested(MAX_EXPR_DEPTH - 1), MAX_EXPR_DEPTH).is_ok());
+    let err = build(&nested(MAX_EXPR_DEPTH + 44), MAX_EXPR_DEPTH).unwrap_err();
+    assert!(err.downcast_ref::<NestingTooDeepError>().is_some());
+    let too_deep = nested(MAX_EXPR_DEPTH + 44);
+    let pair = CozoScriptParser::parse(Rule::expr, &too_deep)
+        .unwrap()
+        .next()
+        .unwrap();
+    assert!(build_expr(pair, &Default::default()).is_err());
+
+    // operators that nest their operands count as levels as well
+    let chain = |op: &str, n: usize| vec!["2"; n].join(op);
+    assert!(build(&chain(" - ", MAX_EXPR_DEPTH - 1), MAX_EXPR_DEPTH).is_ok());
+    for op in [" - ", " ^ ", " / ", " ~ "] {
+        let err = build(&chain(op, MAX_EXPR_DEPTH + 1), MAX_EXPR_DEPTH).unwrap_err();
+        assert!(err.downcast_ref::<NestingTooDeepError>().is_some(), "{op}");
+    }
+    let err = build(&format!("{}1", "-".repeat(MAX_EXPR_DEPTH)), MAX_EXPR_DEPTH).unwrap_err();
+    assert!(err.downcast_ref::<NestingTooDeepError>().is_some());
+    // but chains that become a single node do not
+    for op in [" + ", " * ", " && ", " ++ "] {
+        assert!(
+            build(&chain(op, 10 * MAX_EXPR_DEPTH), MAX_EXPR_DEPTH).is_ok(),
+            "{op}"
+        );
+    }
+
+    // deeper brackets are rejected before the grammar recurses into them
+    let hostile = nested(100_000);
+    let errs = parse_expr_structured(&hostile).unwrap_err();
+    assert_eq!(errs[0].code, "parser::nesting_too_deep");
+    let db = new_cozo_mem().unwrap();
+    let err = db
+        .run_script(&format!("?[a] := a = {hostile}"), Default::default())
+        .unwrap_err();
+    assert!(err.to_string().contains("nested too deeply"), "{err}");
+}
+
+#[test]
+fn deep_expressions_evaluate_without_recursion() {
+    let one = Expr::Const {
+        val: DataValue::from(1),
+        span: Default::default(),
+    };
+    let mut expr = one.clone();
+    for _ in 1..100_000 {
+        expr = Expr::Apply {
+            op: &OP_ADD,
+            args: [expr, one.clone()].into(),
+            span: Default::default(),
+        };
+    }
+    assert_eq!(expr.eval(&vec![]).unwrap(), DataValue::from(100_000));
+    // dropping is still recursive, and would overflow the stack instead
+    std::mem::forget(expr);
+}
+
+#[test]
+fn three_way_comparison() {
+    for (src, expected) in [
+        ("1 <=> 2", -1),
+        ("2 <=> 2", 0),
+        ("3 <=> 2", 1),
+        ("1.5 <=> 2.5", -1),
+        ("2 <=> 2.0", 0),
+        ("2.5 <=> 2", 1),
+        ("'a' <=> 'b'", -1),
+        ("'b' <=> 'b'", 0),
+        ("'b' <=> 'ab'", 1),
+        ("1 + 1 <=> 2", 0),
+        ("null <=> 0", -1),
+        ("null <=> null", 0),
+        ("1 <=> 'a'", -1),
+        ("[1, 'a'] <=> [1, 'b']", -1),
+    ] {
+        let val = parse_expr(src).eval(&vec![]).unwrap();
+        assert_eq!(val, DataValue::from(expected), "{src}");
+    }
+    assert_eq!(expr2str(&parse_expr("cmp(a, b)")), "a <=> b");
+    assert_eq!(expr2str(&parse_expr("(a <=> b) == 0")), "(a <=> b) == 0");
+}
+
+#[test]
+fn identities_are_simplified() {
+    for (src, expected) in [
+        ("length(l) + 0", "length(l)"),
+        ("0 + length(l)", "length(l)"),
+        ("length(l) * 1", "length(l)"),
+        ("length(l) * 0", "0"),
+        ("length(l) * 2 * 0", "0"),
+        ("(a > 1) && true", "a > 1"),
+        ("(a > 1) || false", "a > 1"),
+        ("true && (a > 1) && (b < 2)", "a > 1 && b < 2"),
+    ] {
+        assert_eq!(expr2str(&parse_expr(src)), expected, "{src}");
+    }
+    for src in [
+        // types not known, so `a + 0` could be an error or a float
+        "a + 0",
+        "a && true",
+        "length(l) + 0.0",
+        "'s' + 0",
+        // the side effect of each call must be kept
+        "rand_int(0, 5) * 0",
+        // `x * 0` is NaN for an infinite float
+        "to_float(s) * 0",
+    ] {
+        assert_eq!(expr2str(&parse_expr(src)), src, "{src}");
+    }
+    assert!(parse_expr("'s' + 0").eval(&vec![]).is_err());
+
+    // so for a variable, `a + 0` is computed, giving what the bound value requires
+    let expr = parse_expr("a + 0");
+    let eval = |val: DataValue| {
+        let bindings = BTreeMap::from([(Symbol::new("a", Default::default()), val)]);
+        expr.evaluate(&bindings)
+    };
+    assert_eq!(eval(DataValue::from(5)).unwrap(), DataValue::from(5));
+    assert_eq!(eval(DataValue::from(1.5)).unwrap(), DataValue::from(1.5));
+    assert!(eval(DataValue::from("s")).is_err());
+    assert!(eval(DataValue::Null).is_err());
+}
+
+#[test]
+fn spans_locate_in_lines_and_utf16() {
+    let locate = |src: &str| {
+        let errs = parse_expr_structured(src).unwrap_err();
+        let (offset, len) = errs[0].span;
+        SourceSpan(offset, len).locate(src)
+    };
+
+    let src = "'é😀' ++ 2e_";
+    let (start, end) = locate(src);
+    assert_eq!(src.find("2e_"), Some(12));
+    assert_eq!(
+        start,
+        SourcePosition {
+            line: 0,
+            column: 8,
+            utf16_column: 9,
+            utf16_offset: 9,
+        }
+    );
+    assert_eq!(end.column, 11);
+    assert_eq!(end.utf16_offset, 12);
+
+    let (start, end) = locate("'é😀' ++\n  2e_");
+    assert_eq!(
+        start,
+        SourcePosition {
+            line: 1,
+            column: 2,
+            utf16_column: 2,
+            utf16_offset: 11,
+        }
+    );
+    assert_eq!((end.line, end.column, end.utf16_offset), (1, 5, 14));
+
+    let (start, end) = SourceSpan(0, 100).locate("ab");
+    assert_eq!(start, SourcePosition::default());
+    assert_eq!(end.utf16_offset, 2);
+}
+
+#[test]
+fn chained_comparisons_are_rejected() {
+    let errs = parse_expr_structured("1 == 1 == 1").unwrap_err();
+    assert_eq!(
+        errs,
+        vec![ParseDiagnostic {
+            code: "parser::chained_comparison".to_string(),
+            span: (2, 7),
+            message: "Comparisons cannot be chained".to_string(),
+        }]
+    );
+
+    let pair = CozoScriptParser::parse(Rule::expr, "a < b <= c")
+        .unwrap()
+        .next()
+        .unwrap();
+    let err = build_expr(pair, &Default::default()).unwrap_err();
+    assert_eq!(
+        err.help().unwrap().to_string(),
+        "To compare both, write `a < b && b <= c`"
+    );
+
+    for src in ["a == b != c", "a != b < c", "a <=> b == 0", "[a > b == c]"] {
+        assert!(parse_expr_structured(src).is_err(), "{src}");
+    }
+    for (src, printed) in [
+        ("(1 == 1) == true", "(1 == 1) == true"),
+        ("a == b && b == c", "a == b && b == c"),
+        ("a < b || b > c", "a < b || b > c"),
+        ("(x in l) == false", "(x in l) == false"),
+    ] {
+        assert_eq!(expr2str(&parse_expr(src)), printed, "{src}");
+    }
+    assert_eq!(
+        parse_expr("(1 == 1) == true").eval(&vec![]).unwrap(),
+        DataValue::from(true)
+    );
+}
+
+#[test]
+fn dotted_binding_names() {
+    match parse_expr("a.b.c") {
+        Expr::Binding { var, .. } => assert_eq!(var.name, "a.b.c"),
+        expr => panic!("expected a binding, got {expr:?}"),
+    }
+    assert_eq!(expr2str(&parse_expr("ns.x + _tmp.y_2")), "ns.x + _tmp.y_2");
+    assert_eq!(expr2str(&parse_expr("[1, ..ns.xs]")), "[1] ++ ns.xs");
+    for src in ["a.", "a..b", "a.1", "a. b"] {
+        assert!(parse_expr_structured(src).is_err(), "{src}");
+    }
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            "?[geo.lat, geo.lon] := geo.lat = 1.5, geo.lon = geo.lat * 2",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.headers, ["geo.lat", "geo.lon"]);
+    assert_eq!(res.rows[0], [DataValue::from(1.5), DataValue::from(3.0)]);
+
+    // dotted bindings live alongside dotted relation names
+    db.run_script(
+        "?[id, lat] <- [[1, 2.5]] :create geo.points {id => lat}",
+        Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script(
+            "?[geo.points, geo.lat] := geo.points = 1, *geo.points{id: geo.points, lat: geo.lat}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.rows[0], [DataValue::from(1), DataValue::from(2.5)]);
+}
+
+#[test]
+fn custom_ops_can_be_registered() {
+    fn triple(args: &[DataValue]) -> miette::Result<DataValue> {
+        match args[0].get_int() {
+            Some(i) => Ok(DataValue::from(i * 3)),
+            None => miette::bail!("'triple' requires an int"),
+        }
+    }
+    register_op("triple", 1, false, false, true, triple).unwrap();
+    let op = get_op("triple").unwrap();
+    assert!(!op.is_commutative() && !op.is_associative());
+    assert_eq!(op.identity_element(), None);
+    assert_eq!(op.result_type(&[Some(ValueType::Int)]), None);
+
+    let expr = parse_expr("triple(2) + 1");
+    assert_eq!(expr.eval(&vec![]).unwrap(), DataValue::from(7));
+    assert_eq!(expr2str(&expr), "triple(2) + 1");
+    assert!(parse_expr_structured("triple(1, 2)").is_err());
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script("?[a] := a = triple(14)", Default::default())
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(42));
+
+    assert!(register_op("triple", 1, false, false, true, triple).is_err());
+    assert!(register_op("add", 2, false, false, true, triple).is_err());
+    // would be taken for the compiled pattern of `regex_matches`
+    assert!(register_op("regex", 1, false, false, true, triple).is_err());
+    for name in ["Triple", "2x", "", "if", "tri-ple"] {
+        assert!(
+            register_op(name, 1, false, false, true, triple).is_err(),
+            "{name}"
+        );
+    }
+
+    fn next_serial(_args: &[DataValue]) -> miette::Result<DataValue> {
+        static SERIAL: AtomicI64 = AtomicI64::new(0);
+        Ok(DataValue::from(SERIAL.fetch_add(1, Ordering::Relaxed)))
+    }
+    register_op("next_serial", 0, false, true, false, next_serial).unwrap();
+    let mut expr = parse_expr("[next_serial(), next_serial()]");
+    expr.partial_eval().unwrap();
+    assert!(expr.get_const().is_none());
+    let res = expr.compile();
+    assert!(!res.iter().any(|c| matches!(c, Bytecode::Memoize { .. })));
+    let res = eval_bytecode(&res, &vec![], &mut vec![]).unwrap();
+    let res = res.get_slice().unwrap();
+    assert_ne!(res[0], res[1]);
+}
+
+#[test]
+fn ops_can_be_shared_across_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Op>();
+    assert_send_sync::<&'static Op>();
+    assert_send_sync::<Expr>();
+
+    let expr = parse_expr("max(1, 2) + length([3])");
+    let handle = std::thread::spawn(move || expr.eval(&vec![]).unwrap());
+    assert_eq!(handle.join().unwrap(), DataValue::from(3));
+}
+
+#[test]
+fn non_deterministic_ops_are_not_memoized() {
+    for op in [&OP_ADD, &OP_CONCAT, &OP_LIST] {
+        assert!(op.is_deterministic(), "{op:?}");
+    }
+    for name in ["now", "rand_float", "rand_int", "rand_uuid_v1", "uuid"] {
+        assert!(!get_op(name).unwrap().is_deterministic(), "{name}");
+    }
+
+    // every occurrence of a shared application may fill its slot, so count the slots
+    let memoized = |src: &str| {
+        parse_expr(src)
+            .compile()
+            .iter()
+            .filter_map(|c| match c {
+                Bytecode::Memoize { slot } => Some(*slot),
+                _ => None,
+            })
+            .unique()
+            .count()
+    };
+    assert_eq!(memoized("length(a) + length(a)"), 1);
+    assert_eq!(memoized("now() - now()"), 0);
+    assert_eq!(
+        memoized("[to_string(rand_uuid_v4()), to_string(rand_uuid_v4())]"),
+        0
+    );
+}
+
+#[test]
+fn parsed_and_evaluated_values_order_alike() {
+    // constants are kept as parsed, and evaluation produces the same `DataValue` type
+    let parsed = ["1", "2.5", "'a'", "null"].map(|src| match parse_expr(src) {
+        Expr::Const { val, .. } => val,
+        expr => panic!("expected a constant, got {expr:?}"),
+    });
+    let parsed = DataValue::List(parsed.to_vec());
+    let evaluated = parse_expr("[3 - 2, 5 / 2, concat('a'), nullif(1, 1)]")
+        .eval(&vec![])
+        .unwrap();
+    assert_eq!(parsed, evaluated);
+
+    let mut sorted = vec![
+        DataValue::from("b"),
+        evaluated.clone(),
+        DataValue::from(0),
+        parsed.clone(),
+    ];
+    sorted.sort();
+    assert_eq!(sorted[2], sorted[3]);
+    assert_eq!(sorted[2], parsed);
+    let set: BTreeSet<_> = sorted.into_iter().collect();
+    assert_eq!(set.len(), 3);
+    assert!(set.contains(&evaluated));
+}
+
+#[test]
+fn nan_and_infinity_values() {
+    let eval = |src: &str| parse_expr(src).eval(&vec![]).unwrap();
+    assert!(eval("to_float('NAN')").get_float().unwrap().is_nan());
+    assert_eq!(eval("to_float('INF')"), DataValue::from(f64::INFINITY));
+    assert_eq!(eval("-to_float('INF')"), DataValue::from(f64::NEG_INFINITY));
+    assert_eq!(eval("to_float('INF') > 1e308"), DataValue::from(true));
+    assert_eq!(eval("to_float('NEG_INF') < -1e308"), DataValue::from(true));
+    assert_eq!(
+        eval("to_float('NAN') == to_float('NAN')"),
+        DataValue::from(false)
+    );
+    assert_eq!(
+        eval("to_float('NAN') != to_float('NAN')"),
+        DataValue::from(true)
+    );
+
+    // constants print as applications evaluating to them again
+    for val in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+        let expr = Expr::Const {
+            val: DataValue::from(val),
+            span: Default::default(),
+        };
+        let printed = expr2str(&expr);
+        assert!(printed.starts_with("to_float("), "{printed}");
+        let reparsed = eval(&printed).get_float().unwrap();
+        assert!(
+            reparsed == val || reparsed.is_nan() && val.is_nan(),
+            "{printed}"
+        );
+    }
+
+    // `nan` and `inf` are names like any other
+    assert_eq!(parse_expr("inf + nan").bindings().len(), 2);
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script("?[inf, nan] := inf = 1, nan = inf + 1", Default::default())
+        .unwrap();
+    assert_eq!(res.rows[0], [DataValue::from(1), DataValue::from(2)]);
+}
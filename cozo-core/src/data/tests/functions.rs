@@ -7,11 +7,13 @@
  */
 
 use approx::AbsDiffEq;
+use itertools::Itertools;
 use num_traits::FloatConst;
 use regex::Regex;
 
+use crate::data::expr::get_op;
 use crate::data::functions::*;
-use crate::data::value::{DataValue, RegexWrapper};
+use crate::data::value::{DataValue, Num, RegexWrapper};
 use crate::new_cozo_mem;
 
 #[test]
@@ -32,6 +34,44 @@ fn test_add() {
     );
 }
 
+#[test]
+fn test_numeric_promotion() {
+    let cases = [
+        (
+            DataValue::from(1),
+            DataValue::from(2),
+            Some(NumericPromotion::IntInt),
+        ),
+        (
+            DataValue::from(1),
+            DataValue::from(2.5),
+            Some(NumericPromotion::IntFloat),
+        ),
+        (
+            DataValue::from(1.5),
+            DataValue::from(2),
+            Some(NumericPromotion::IntFloat),
+        ),
+        (
+            DataValue::from(1.5),
+            DataValue::from(2.5),
+            Some(NumericPromotion::FloatFloat),
+        ),
+        (DataValue::from(1), DataValue::Null, None),
+        (DataValue::Str("1".into()), DataValue::from(1), None),
+    ];
+    for (a, b, expected) in cases {
+        assert_eq!(numeric_promotion(&a, &b), expected);
+        // the prediction agrees with what addition actually produces
+        if let Ok(DataValue::Num(res)) = op_add(&[a, b]) {
+            assert_eq!(
+                matches!(res, Num::Int(_)),
+                expected == Some(NumericPromotion::IntInt)
+            );
+        }
+    }
+}
+
 #[test]
 fn test_sub() {
     assert_eq!(
@@ -65,6 +105,110 @@ fn test_mul() {
     );
 }
 
+#[test]
+fn test_mixed_arithmetic() {
+    let i = DataValue::from(3);
+    let f = DataValue::from(0.5);
+    for (op, ii, if_, fi, ff) in [
+        (
+            op_add as fn(&[DataValue]) -> miette::Result<DataValue>,
+            DataValue::from(6),
+            DataValue::from(3.5),
+            DataValue::from(3.5),
+            DataValue::from(1.0),
+        ),
+        (
+            op_sub,
+            DataValue::from(0),
+            DataValue::from(2.5),
+            DataValue::from(-2.5),
+            DataValue::from(0.0),
+        ),
+        (
+            op_mul,
+            DataValue::from(9),
+            DataValue::from(1.5),
+            DataValue::from(1.5),
+            DataValue::from(0.25),
+        ),
+        (
+            op_div,
+            DataValue::from(1.0),
+            DataValue::from(6.0),
+            DataValue::from(1.0 / 6.0),
+            DataValue::from(1.0),
+        ),
+    ] {
+        assert_eq!(op(&[i.clone(), i.clone()]).unwrap(), ii);
+        assert_eq!(op(&[i.clone(), f.clone()]).unwrap(), if_);
+        assert_eq!(op(&[f.clone(), i.clone()]).unwrap(), fi);
+        assert_eq!(op(&[f.clone(), f.clone()]).unwrap(), ff);
+        assert!(op(&[i.clone(), DataValue::from("3")]).is_err());
+        assert!(op(&[DataValue::Null, i.clone()]).is_err());
+    }
+    // a float operand makes the result a float even when it contributes nothing
+    assert_eq!(
+        op_add(&[DataValue::from(1), DataValue::from(0.0)]).unwrap(),
+        DataValue::from(1.0)
+    );
+    assert_eq!(
+        op_mul(&[DataValue::from(2), DataValue::from(1.0)]).unwrap(),
+        DataValue::from(2.0)
+    );
+    assert_eq!(
+        op_add(&[DataValue::from(1), DataValue::from(2), DataValue::from(0.5)]).unwrap(),
+        DataValue::from(3.5)
+    );
+}
+
+#[test]
+fn test_try_div() {
+    assert_eq!(
+        op_try_div(&[DataValue::from(1), DataValue::from(2)]).unwrap(),
+        DataValue::from(0.5)
+    );
+    assert_eq!(
+        op_try_div(&[DataValue::from(1), DataValue::from(0)]).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(
+        op_try_div(&[DataValue::from(1.5), DataValue::from(-0.0)]).unwrap(),
+        DataValue::Null
+    );
+    assert!(op_try_div(&[DataValue::from("1"), DataValue::from(0)]).is_err());
+    assert!(op_try_div(&[DataValue::from(1), DataValue::Null]).is_err());
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            "?[a, b] := a = try_div(1, 0), b = try_div(6, 3)",
+            Default::default(),
+        )
+        .unwrap()
+        .rows;
+    assert_eq!(res[0], vec![DataValue::Null, DataValue::from(2.0)]);
+}
+
+#[test]
+fn test_int_div() {
+    assert_eq!(
+        op_int_div(&[DataValue::from(7), DataValue::from(2)]).unwrap(),
+        DataValue::from(3)
+    );
+    assert_eq!(
+        op_int_div(&[DataValue::from(-7), DataValue::from(2)]).unwrap(),
+        DataValue::from(-3)
+    );
+    assert_eq!(
+        op_int_div(&[DataValue::from(7), DataValue::from(-2)]).unwrap(),
+        DataValue::from(-3)
+    );
+    assert!(op_int_div(&[DataValue::from(7), DataValue::from(0)]).is_err());
+    assert!(op_int_div(&[DataValue::from(i64::MIN), DataValue::from(-1)]).is_err());
+    assert!(op_int_div(&[DataValue::from(7.0), DataValue::from(2)]).is_err());
+    assert!(op_int_div(&[DataValue::from(7), DataValue::from(2.0)]).is_err());
+}
+
 #[test]
 fn test_div() {
     assert_eq!(
@@ -103,6 +247,32 @@ fn test_eq_neq() {
         op_eq(&[DataValue::from(123), DataValue::from(123.1)]).unwrap(),
         DataValue::from(false)
     );
+
+    let nan = DataValue::from(f64::NAN);
+    for (l, r, eq, lt, gt) in [
+        (DataValue::from(1), DataValue::from(1.0), true, false, false),
+        (DataValue::from(1.0), DataValue::from(1), true, false, false),
+        (DataValue::from(1), DataValue::from(1.5), false, true, false),
+        (DataValue::from(2.5), DataValue::from(2), false, false, true),
+        (
+            DataValue::from(0.0),
+            DataValue::from(-0.0),
+            true,
+            false,
+            false,
+        ),
+        (nan.clone(), nan.clone(), false, false, false),
+        (nan.clone(), DataValue::from(1), false, false, false),
+        (DataValue::from(1.0), nan.clone(), false, false, false),
+    ] {
+        let args = [l, r];
+        assert_eq!(op_eq(&args).unwrap(), DataValue::from(eq), "{args:?}");
+        assert_eq!(op_neq(&args).unwrap(), DataValue::from(!eq), "{args:?}");
+        assert_eq!(op_lt(&args).unwrap(), DataValue::from(lt), "{args:?}");
+        assert_eq!(op_gt(&args).unwrap(), DataValue::from(gt), "{args:?}");
+        assert_eq!(op_le(&args).unwrap(), DataValue::from(lt || eq), "{args:?}");
+        assert_eq!(op_ge(&args).unwrap(), DataValue::from(gt || eq), "{args:?}");
+    }
 }
 
 #[test]
@@ -142,6 +312,108 @@ fn test_is_in() {
     );
 }
 
+#[test]
+fn test_in() {
+    assert_eq!(
+        op_in(&[
+            DataValue::from(1),
+            DataValue::List(vec![DataValue::from(1.0), DataValue::from(2)])
+        ])
+        .unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_in(&[
+            DataValue::from(3),
+            DataValue::List(vec![DataValue::from(1), DataValue::from(2)])
+        ])
+        .unwrap(),
+        DataValue::from(false)
+    );
+    assert_eq!(
+        op_in(&[DataValue::Null, DataValue::List(vec![DataValue::Null])]).unwrap(),
+        DataValue::from(true)
+    );
+    assert!(op_in(&[DataValue::from(1), DataValue::from(1)]).is_err());
+
+    // nested lists are compared element by element, as in `==`
+    let pair = |a: i64, b: f64| DataValue::List(vec![DataValue::from(a), DataValue::from(b)]);
+    let haystack = DataValue::List(vec![pair(1, 2.), DataValue::List(vec![pair(3, 4.)])]);
+    for (needle, found) in [
+        (pair(1, 2.), true),
+        (pair(2, 1.), false),
+        (DataValue::List(vec![DataValue::from(1)]), false),
+        (DataValue::List(vec![pair(3, 4.)]), true),
+        (pair(3, 4.), false),
+    ] {
+        assert_eq!(
+            op_in(&[needle.clone(), haystack.clone()]).unwrap(),
+            DataValue::from(found),
+            "{needle:?}"
+        );
+        assert_eq!(
+            op_eq(&[needle.clone(), needle.clone()]).unwrap(),
+            DataValue::from(true)
+        );
+    }
+    assert_eq!(
+        op_eq(&[
+            DataValue::List(vec![DataValue::from(f64::NAN)]),
+            DataValue::List(vec![DataValue::from(f64::NAN)])
+        ])
+        .unwrap(),
+        DataValue::from(false)
+    );
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            "?[a, b] := a = 2 in [1, 2, 3], b = 1 + 3 in [1, 2, 3]",
+            Default::default(),
+        )
+        .unwrap()
+        .rows;
+    assert_eq!(res[0][0], DataValue::from(true));
+    assert_eq!(res[0][1], DataValue::from(false));
+    let res = db
+        .run_script(
+            "?[a, b] := a = [1, 2] in [[1, 2], [3]], b = [1, 3] in [[1, 2], [3]]",
+            Default::default(),
+        )
+        .unwrap()
+        .rows;
+    assert_eq!(res[0], vec![DataValue::from(true), DataValue::from(false)]);
+}
+
+#[test]
+fn test_cross_type_comparison() {
+    // comparison operators are strict about types, apart from int/float promotion
+    for op in [op_lt, op_le, op_gt, op_ge] {
+        assert!(op(&[DataValue::from("a"), DataValue::from(1)]).is_err());
+        assert!(op(&[DataValue::from(1), DataValue::from("a")]).is_err());
+        assert!(op(&[DataValue::from(true), DataValue::from(1)]).is_err());
+        assert!(op(&[DataValue::from(1), DataValue::from(1.5)]).is_ok());
+    }
+    // the cross-type rank order is what sorting uses
+    assert_eq!(
+        op_sorted(&[DataValue::List(vec![
+            DataValue::from("a"),
+            DataValue::from(1),
+            DataValue::Null,
+        ])])
+        .unwrap(),
+        DataValue::List(vec![
+            DataValue::Null,
+            DataValue::from(1),
+            DataValue::from("a"),
+        ])
+    );
+    let db = new_cozo_mem().unwrap();
+    assert!(db
+        .run_script(r#"?[a] := a = "a" < 1"#, Default::default())
+        .is_err());
+}
+
 #[test]
 fn test_comparators() {
     assert_eq!(
@@ -247,6 +519,119 @@ fn test_comparators() {
     assert!(op_lt(&[DataValue::Null, DataValue::from(true)]).is_err());
 }
 
+#[test]
+fn test_between() {
+    assert_eq!(
+        op_between(&[DataValue::from(2), DataValue::from(1), DataValue::from(3)]).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_between(&[DataValue::from(1), DataValue::from(1.0), DataValue::from(3)]).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_between(&[DataValue::from(3.0), DataValue::from(1), DataValue::from(3)]).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_between(&[DataValue::from(4), DataValue::from(1), DataValue::from(3)]).unwrap(),
+        DataValue::from(false)
+    );
+    assert_eq!(
+        op_between(&[DataValue::from(2), DataValue::from(3), DataValue::from(1)]).unwrap(),
+        DataValue::from(false)
+    );
+    assert_eq!(
+        op_between(&[
+            DataValue::from("b"),
+            DataValue::from("a"),
+            DataValue::from("c")
+        ])
+        .unwrap(),
+        DataValue::from(true)
+    );
+    assert!(op_between(&[DataValue::from(1), DataValue::from("a"), DataValue::from(3)]).is_err());
+}
+
+#[test]
+fn test_list_reductions() {
+    let ints = DataValue::List(vec![
+        DataValue::from(1),
+        DataValue::from(2),
+        DataValue::from(3),
+    ]);
+    let mixed = DataValue::List(vec![DataValue::from(1), DataValue::from(2.5)]);
+    let empty = DataValue::List(vec![]);
+
+    assert_eq!(op_sum(&[ints.clone()]).unwrap(), DataValue::from(6));
+    assert_eq!(op_sum(&[mixed.clone()]).unwrap(), DataValue::from(3.5));
+    assert_eq!(op_sum(&[empty.clone()]).unwrap(), DataValue::from(0));
+
+    assert_eq!(op_product(&[ints.clone()]).unwrap(), DataValue::from(6));
+    assert_eq!(op_product(&[mixed.clone()]).unwrap(), DataValue::from(2.5));
+    assert_eq!(op_product(&[empty.clone()]).unwrap(), DataValue::from(1));
+    assert_eq!(
+        op_sum(&[empty.clone()]).unwrap(),
+        OP_ADD.identity_element().unwrap()
+    );
+    assert_eq!(
+        op_product(&[empty.clone()]).unwrap(),
+        OP_MUL.identity_element().unwrap()
+    );
+    assert_eq!(OP_AND.identity_element(), Some(DataValue::from(true)));
+    assert_eq!(OP_OR.identity_element(), Some(DataValue::from(false)));
+    assert_eq!(OP_SUB.identity_element(), None);
+
+    assert_eq!(op_avg(&[ints]).unwrap(), DataValue::from(2.0));
+    assert_eq!(op_avg(&[mixed]).unwrap(), DataValue::from(1.75));
+    assert_eq!(op_avg(&[empty]).unwrap(), DataValue::Null);
+
+    let bad = DataValue::List(vec![DataValue::from(1), DataValue::from("2")]);
+    for op in [op_sum, op_product, op_avg] {
+        assert!(op(&[bad.clone()]).is_err());
+        assert!(op(&[DataValue::from(1)]).is_err());
+    }
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            "?[s, p, a] := s = sum([1, 2, 3]), p = product([1, 2, 3]), a = avg([1, 2, 3])",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.rows[0],
+        vec![DataValue::from(6), DataValue::from(6), DataValue::from(2.0)]
+    );
+}
+
+#[test]
+fn test_range() {
+    let range = |args: &[i64]| op_range(&args.iter().map(|i| DataValue::from(*i)).collect_vec());
+    let list = |items: &[i64]| DataValue::List(items.iter().map(|i| DataValue::from(*i)).collect());
+    assert_eq!(range(&[0, 3]).unwrap(), list(&[0, 1, 2]));
+    assert_eq!(range(&[1, 10, 3]).unwrap(), list(&[1, 4, 7]));
+    assert_eq!(range(&[1, 11, 5]).unwrap(), list(&[1, 6]));
+    assert_eq!(range(&[5, 0, -2]).unwrap(), list(&[5, 3, 1]));
+    assert_eq!(range(&[5, 5]).unwrap(), list(&[]));
+    assert_eq!(range(&[5, 0]).unwrap(), list(&[]));
+    assert_eq!(range(&[0, 5, -1]).unwrap(), list(&[]));
+    assert_eq!(
+        range(&[i64::MAX - 1, i64::MAX]).unwrap(),
+        list(&[i64::MAX - 1])
+    );
+    assert_eq!(
+        range(&[i64::MIN, i64::MAX, i64::MAX]).unwrap(),
+        list(&[i64::MIN, -1, i64::MAX - 1])
+    );
+    assert!(range(&[0, 5, 0]).is_err());
+    assert!(range(&[0, 5, 1, 1]).is_err());
+    assert!(op_range(&[DataValue::from(0), DataValue::from(1.5)]).is_err());
+    let err = range(&[0, 10_000_000_000]).unwrap_err();
+    assert!(err.to_string().contains("would be longer than"), "{err}");
+    assert!(range(&[i64::MAX, i64::MIN, -1]).is_err());
+}
+
 #[test]
 fn test_max_min() {
     assert_eq!(op_max(&[DataValue::from(1),]).unwrap(), DataValue::from(1));
@@ -316,6 +701,73 @@ fn test_max_min() {
     assert!(op_max(&[DataValue::from(true)]).is_err());
 }
 
+#[test]
+fn test_max_min_nan() {
+    let nan = DataValue::from(f64::NAN);
+    let is_nan = |v: DataValue| v.get_float().unwrap().is_nan();
+    let args = [DataValue::from(1), nan.clone(), DataValue::from(3.5)];
+
+    // NaN propagates through `max` and `min`, whatever its position
+    assert!(is_nan(op_max(&args).unwrap()));
+    assert!(is_nan(op_min(&args).unwrap()));
+    assert!(is_nan(op_max(&[nan.clone(), DataValue::from(1)]).unwrap()));
+    assert!(is_nan(
+        op_min(&[DataValue::from(-f64::NAN), DataValue::from(1)]).unwrap()
+    ));
+
+    // and is skipped by the `_ignore_nan` variants
+    assert_eq!(op_max_ignore_nan(&args).unwrap(), DataValue::from(3.5));
+    assert_eq!(op_min_ignore_nan(&args).unwrap(), DataValue::from(1));
+    assert_eq!(
+        op_min_ignore_nan(&[
+            nan.clone(),
+            DataValue::from(2),
+            DataValue::from(f64::NEG_INFINITY)
+        ])
+        .unwrap(),
+        DataValue::from(f64::NEG_INFINITY)
+    );
+    assert!(is_nan(
+        op_max_ignore_nan(&[nan.clone(), nan.clone()]).unwrap()
+    ));
+    assert!(is_nan(op_min_ignore_nan(&[nan.clone()]).unwrap()));
+    assert_eq!(
+        op_max_ignore_nan(&[DataValue::from(1), DataValue::from(2)]).unwrap(),
+        DataValue::from(2)
+    );
+
+    // a NaN does not hide type errors in later arguments
+    assert!(op_max(&[nan.clone(), DataValue::from("a")]).is_err());
+    assert!(op_min_ignore_nan(&[nan, DataValue::Null]).is_err());
+}
+
+#[test]
+fn test_greatest_least() {
+    let one = DataValue::from(1);
+    let three = DataValue::from(3);
+    let null = DataValue::Null;
+    assert_eq!(
+        op_greatest(&[one.clone(), null.clone(), three.clone()]).unwrap(),
+        three
+    );
+    assert_eq!(
+        op_least(&[null.clone(), three.clone(), one.clone()]).unwrap(),
+        one
+    );
+    assert_eq!(op_least(&[null.clone(), null.clone()]).unwrap(), null);
+    assert_eq!(op_greatest(&[null.clone()]).unwrap(), null);
+    assert_eq!(
+        op_greatest(&[DataValue::from(2.5), three.clone()]).unwrap(),
+        three
+    );
+    assert_eq!(
+        op_greatest(&[DataValue::from("b"), DataValue::from("ab")]).unwrap(),
+        DataValue::from("b")
+    );
+    assert!(op_greatest(&[one.clone(), DataValue::from("1")]).is_err());
+    assert!(op_least(&[DataValue::from(true), null, one]).is_err());
+}
+
 #[test]
 fn test_minus() {
     assert_eq!(
@@ -529,6 +981,67 @@ fn test_pow() {
         op_pow(&[DataValue::from(2), DataValue::from(10)]).unwrap(),
         DataValue::from(1024.0)
     );
+    assert_eq!(
+        op_pow(&[DataValue::from(0), DataValue::from(0)]).unwrap(),
+        DataValue::from(1.0)
+    );
+    assert_eq!(
+        op_pow(&[DataValue::from(0.0), DataValue::from(2)]).unwrap(),
+        DataValue::from(0.0)
+    );
+    assert_eq!(
+        op_pow(&[DataValue::from(2), DataValue::from(-1)]).unwrap(),
+        DataValue::from(0.5)
+    );
+    assert!(op_pow(&[DataValue::from(0), DataValue::from(-1)]).is_err());
+    assert!(op_pow(&[DataValue::from(0.0), DataValue::from(-0.5)]).is_err());
+    assert!(op_pow(&[DataValue::from(-0.0), DataValue::from(-2)]).is_err());
+
+    let db = new_cozo_mem().unwrap();
+    assert!(db
+        .run_script("?[a] := a = 0 ^ -1", Default::default())
+        .is_err());
+}
+
+#[test]
+fn test_lerp_clamp01() {
+    let lerp = |a: DataValue, b: DataValue, t: DataValue| op_lerp(&[a, b, t]).unwrap();
+    assert_eq!(
+        lerp(
+            DataValue::from(0),
+            DataValue::from(10),
+            DataValue::from(0.5)
+        ),
+        DataValue::from(5.0)
+    );
+    assert_eq!(
+        lerp(DataValue::from(0), DataValue::from(10), DataValue::from(0)),
+        DataValue::from(0.0)
+    );
+    // no clamping outside of [0, 1]
+    assert_eq!(
+        lerp(DataValue::from(0), DataValue::from(10), DataValue::from(2)),
+        DataValue::from(20.0)
+    );
+    assert_eq!(
+        lerp(
+            DataValue::from(1.5),
+            DataValue::from(-0.5),
+            DataValue::from(-1)
+        ),
+        DataValue::from(3.5)
+    );
+    assert!(op_lerp(&[DataValue::from(0), DataValue::Null, DataValue::from(0.5)]).is_err());
+
+    let clamp01 = |x: DataValue| op_clamp01(&[x]).unwrap();
+    assert_eq!(clamp01(DataValue::from(1.5)), DataValue::from(1.0));
+    assert_eq!(clamp01(DataValue::from(-3)), DataValue::from(0.0));
+    assert_eq!(clamp01(DataValue::from(0.25)), DataValue::from(0.25));
+    assert!(clamp01(DataValue::from(f64::NAN))
+        .get_float()
+        .unwrap()
+        .is_nan());
+    assert!(op_clamp01(&[DataValue::from("1")]).is_err());
 }
 
 #[test]
@@ -539,6 +1052,22 @@ fn test_mod() {
     );
 }
 
+#[test]
+fn test_gcd_lcm() {
+    let gcd = |a: i64, b: i64| op_gcd(&[DataValue::from(a), DataValue::from(b)]);
+    let lcm = |a: i64, b: i64| op_lcm(&[DataValue::from(a), DataValue::from(b)]);
+    assert_eq!(gcd(12, 18).unwrap(), DataValue::from(6));
+    assert_eq!(gcd(-12, 18).unwrap(), DataValue::from(6));
+    assert_eq!(gcd(0, 5).unwrap(), DataValue::from(5));
+    assert_eq!(gcd(0, 0).unwrap(), DataValue::from(0));
+    assert_eq!(lcm(4, 6).unwrap(), DataValue::from(12));
+    assert_eq!(lcm(-4, 6).unwrap(), DataValue::from(12));
+    assert_eq!(lcm(0, 6).unwrap(), DataValue::from(0));
+    assert!(lcm(i64::MAX, i64::MAX - 1).is_err());
+    assert!(gcd(i64::MIN, 0).is_err());
+    assert!(op_gcd(&[DataValue::from(1.0), DataValue::from(2)]).is_err());
+}
+
 #[test]
 fn test_boolean() {
     assert_eq!(op_and(&[]).unwrap(), DataValue::from(true));
@@ -555,6 +1084,90 @@ fn test_boolean() {
         op_negate(&[DataValue::from(false)]).unwrap(),
         DataValue::from(true)
     );
+    assert_eq!(op_negate(&[DataValue::Null]).unwrap(), DataValue::Null);
+    assert!(op_negate(&[DataValue::from(0)]).is_err());
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            "?[a, b, c] := a = not(true), b = not(null), c = !null",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.rows[0],
+        vec![DataValue::from(false), DataValue::Null, DataValue::Null]
+    );
+
+    let t = DataValue::from(true);
+    let f = DataValue::from(false);
+    let n = DataValue::Null;
+    for (l, r, and, or) in [
+        (&t, &t, &t, &t),
+        (&t, &f, &f, &t),
+        (&t, &n, &n, &t),
+        (&f, &t, &f, &t),
+        (&f, &f, &f, &f),
+        (&f, &n, &f, &n),
+        (&n, &t, &n, &t),
+        (&n, &f, &f, &n),
+        (&n, &n, &n, &n),
+    ] {
+        assert_eq!(&op_and(&[l.clone(), r.clone()]).unwrap(), and);
+        assert_eq!(&op_or(&[l.clone(), r.clone()]).unwrap(), or);
+    }
+    assert!(op_and(&[DataValue::from(1), t.clone()]).is_err());
+    assert!(op_or(&[f, DataValue::from("")]).is_err());
+}
+
+#[test]
+fn test_xor() {
+    let t = DataValue::from(true);
+    let f = DataValue::from(false);
+    let n = DataValue::Null;
+    for (l, r, xor) in [
+        (&t, &t, &f),
+        (&t, &f, &t),
+        (&t, &n, &n),
+        (&f, &t, &t),
+        (&f, &f, &f),
+        (&f, &n, &n),
+        (&n, &t, &n),
+        (&n, &f, &n),
+        (&n, &n, &n),
+    ] {
+        assert_eq!(&op_xor(&[l.clone(), r.clone()]).unwrap(), xor);
+    }
+    assert!(op_xor(&[n.clone(), DataValue::from(1)]).is_err());
+    assert!(op_xor(&[DataValue::from(0), t]).is_err());
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script("?[a] := a = xor(1 > 2, 2 > 1)", Default::default())
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(true));
+}
+
+#[test]
+fn test_int_bits() {
+    let apply = |op: fn(&[DataValue]) -> miette::Result<DataValue>, i: i64| {
+        op(&[DataValue::from(i)]).unwrap()
+    };
+    assert_eq!(apply(op_bit_count, 7), DataValue::from(3));
+    assert_eq!(apply(op_bit_count, 0), DataValue::from(0));
+    assert_eq!(apply(op_leading_zeros, 1), DataValue::from(63));
+    assert_eq!(apply(op_leading_zeros, 0), DataValue::from(64));
+    assert_eq!(apply(op_trailing_zeros, 8), DataValue::from(3));
+    assert_eq!(apply(op_trailing_zeros, 0), DataValue::from(64));
+    // negative values are taken as their two's complement bit patterns
+    assert_eq!(apply(op_bit_count, -1), DataValue::from(64));
+    assert_eq!(apply(op_leading_zeros, -1), DataValue::from(0));
+    assert_eq!(apply(op_trailing_zeros, i64::MIN), DataValue::from(63));
+    assert_eq!(apply(op_bit_count, i64::MIN), DataValue::from(1));
+    for op in [op_bit_count, op_leading_zeros, op_trailing_zeros] {
+        assert!(op(&[DataValue::from(1.0)]).is_err());
+    }
+    assert_eq!(get_op("popcount"), Some(&OP_BIT_COUNT));
 }
 
 #[test]
@@ -657,6 +1270,30 @@ fn test_casings() {
         op_uppercase(&[DataValue::Str("naïve".into())]).unwrap(),
         DataValue::Str("NAÏVE".into())
     );
+
+    let capitalize = |s: &str| op_capitalize(&[DataValue::from(s)]).unwrap();
+    assert_eq!(capitalize("hELLO wORLD"), DataValue::from("Hello world"));
+    assert_eq!(capitalize("élan"), DataValue::from("Élan"));
+    assert_eq!(capitalize("ßig"), DataValue::from("SSig"));
+    assert_eq!(capitalize("  hELLO"), DataValue::from("  hello"));
+    assert_eq!(capitalize(""), DataValue::from(""));
+    assert!(op_capitalize(&[DataValue::from(1)]).is_err());
+
+    let title_case = |s: &str| op_title_case(&[DataValue::from(s)]).unwrap();
+    assert_eq!(
+        title_case("the quick\tbrown  fox"),
+        DataValue::from("The Quick\tBrown  Fox")
+    );
+    assert_eq!(
+        title_case("  leading space"),
+        DataValue::from("  Leading Space")
+    );
+    assert_eq!(
+        title_case("ünïcode émoji 😀ok"),
+        DataValue::from("Ünïcode Émoji 😀ok")
+    );
+    assert_eq!(title_case("mIxEd cAsE"), DataValue::from("MIxEd CAsE"));
+    assert!(op_title_case(&[DataValue::Null]).is_err());
 }
 
 #[test]
@@ -676,39 +1313,178 @@ fn test_trim() {
 }
 
 #[test]
-fn test_starts_ends_with() {
+fn test_index_of() {
+    let index_of = |a: DataValue, b: DataValue| op_index_of(&[a, b]).unwrap();
     assert_eq!(
-        op_starts_with(&[
-            DataValue::Str("abcdef".into()),
-            DataValue::Str("abc".into())
-        ])
-        .unwrap(),
-        DataValue::from(true)
+        index_of(DataValue::from("hello"), DataValue::from("ll")),
+        DataValue::from(2)
     );
+    // counted in chars, not bytes
     assert_eq!(
-        op_starts_with(&[DataValue::Str("abcdef".into()), DataValue::Str("bc".into())]).unwrap(),
-        DataValue::from(false)
+        index_of(DataValue::from("héllo"), DataValue::from("l")),
+        DataValue::from(2)
     );
     assert_eq!(
-        op_ends_with(&[
-            DataValue::Str("abcdef".into()),
-            DataValue::Str("def".into())
-        ])
-        .unwrap(),
-        DataValue::from(true)
+        index_of(DataValue::from("hello"), DataValue::from("")),
+        DataValue::from(0)
     );
     assert_eq!(
-        op_ends_with(&[DataValue::Str("abcdef".into()), DataValue::Str("bc".into())]).unwrap(),
-        DataValue::from(false)
+        index_of(DataValue::from("hello"), DataValue::from("x")),
+        DataValue::from(-1)
+    );
+    let list = DataValue::List(vec![
+        DataValue::from("a"),
+        DataValue::from(2),
+        DataValue::from(2),
+    ]);
+    assert_eq!(
+        index_of(list.clone(), DataValue::from(2.0)),
+        DataValue::from(1)
     );
+    assert_eq!(index_of(list, DataValue::from("b")), DataValue::from(-1));
+    assert!(op_index_of(&[DataValue::from("a"), DataValue::from(1)]).is_err());
+    assert!(op_index_of(&[DataValue::from(1), DataValue::from(1)]).is_err());
 }
 
 #[test]
-fn test_regex() {
-    assert_eq!(
-        op_regex_matches(&[
-            DataValue::Str("abcdef".into()),
-            DataValue::Regex(RegexWrapper(Regex::new("c.e").unwrap()))
+fn test_string_count() {
+    let count = |a: &str, b: &str| {
+        op_string_count(&[DataValue::from(a), DataValue::from(b)])
+            .unwrap()
+            .get_int()
+            .unwrap()
+    };
+    assert_eq!(count("banana", "an"), 2);
+    assert_eq!(count("banana", "x"), 0);
+    // occurrences do not overlap
+    assert_eq!(count("aaa", "aa"), 1);
+    assert_eq!(count("aaaa", "aa"), 2);
+    // an empty needle matches around every char, as in Python
+    assert_eq!(count("héllo", ""), 6);
+    assert_eq!(count("", ""), 1);
+    assert_eq!(count("", "a"), 0);
+    assert!(op_string_count(&[DataValue::from("a"), DataValue::from(1)]).is_err());
+    assert!(op_string_count(&[DataValue::Null, DataValue::from("a")]).is_err());
+}
+
+#[test]
+fn test_levenshtein() {
+    let dist = |a: &str, b: &str| {
+        op_levenshtein(&[DataValue::from(a), DataValue::from(b)])
+            .unwrap()
+            .get_int()
+            .unwrap()
+    };
+    assert_eq!(dist("kitten", "sitting"), 3);
+    assert_eq!(dist("sitting", "kitten"), 3);
+    assert_eq!(dist("flaw", "lawn"), 2);
+    assert_eq!(dist("same", "same"), 0);
+    assert_eq!(dist("", "abc"), 3);
+    assert_eq!(dist("abc", ""), 3);
+    assert_eq!(dist("", ""), 0);
+    // counted in chars, not bytes
+    assert_eq!(dist("café", "cafe"), 1);
+    assert_eq!(dist("😀", "😁"), 1);
+
+    let long = DataValue::from("a".repeat(10_000));
+    let err = op_levenshtein(&[long.clone(), long]).unwrap_err();
+    assert!(err.to_string().contains("too long"), "{err}");
+    assert!(op_levenshtein(&[DataValue::from("a"), DataValue::from(1)]).is_err());
+}
+
+#[test]
+fn test_string_repeat() {
+    assert_eq!(
+        op_string_repeat(&[DataValue::from("-"), DataValue::from(10)]).unwrap(),
+        DataValue::from("----------")
+    );
+    assert_eq!(
+        op_string_repeat(&[DataValue::from("ab"), DataValue::from(0)]).unwrap(),
+        DataValue::from("")
+    );
+    assert!(op_string_repeat(&[DataValue::from("ab"), DataValue::from(-1)]).is_err());
+    assert!(op_string_repeat(&[DataValue::from("ab"), DataValue::from(2.0)]).is_err());
+    let err =
+        op_string_repeat(&[DataValue::from("x"), DataValue::from(10_000_000_000i64)]).unwrap_err();
+    assert!(err.to_string().contains("would be larger than"), "{err}");
+    assert!(op_string_repeat(&[DataValue::from("xy"), DataValue::from(i64::MAX)]).is_err());
+    // repeating the empty string is always fine
+    assert_eq!(
+        op_string_repeat(&[DataValue::from(""), DataValue::from(i64::MAX)]).unwrap(),
+        DataValue::from("")
+    );
+}
+
+#[test]
+fn test_pad() {
+    let pad = |f: fn(&[DataValue]) -> miette::Result<DataValue>, s: &str, w: i64, fill: &str| {
+        f(&[
+            DataValue::from(s),
+            DataValue::from(w),
+            DataValue::from(fill),
+        ])
+    };
+    assert_eq!(
+        pad(op_pad_left, "42", 5, "0").unwrap(),
+        DataValue::from("00042")
+    );
+    assert_eq!(
+        pad(op_pad_right, "ab", 4, ".").unwrap(),
+        DataValue::from("ab..")
+    );
+    // width counts characters, not bytes
+    assert_eq!(
+        pad(op_pad_left, "é", 3, "·").unwrap(),
+        DataValue::from("··é")
+    );
+    assert_eq!(
+        pad(op_pad_right, "abcdef", 3, " ").unwrap(),
+        DataValue::from("abcdef")
+    );
+    assert_eq!(
+        pad(op_pad_left, "abc", 3, " ").unwrap(),
+        DataValue::from("abc")
+    );
+    assert!(pad(op_pad_left, "a", 3, "ab").is_err());
+    assert!(pad(op_pad_right, "a", 3, "").is_err());
+    assert!(pad(op_pad_left, "a", -1, " ").is_err());
+    assert!(pad(op_pad_left, "a", i64::MAX, " ").is_err());
+}
+
+#[test]
+fn test_starts_ends_with() {
+    assert_eq!(
+        op_starts_with(&[
+            DataValue::Str("abcdef".into()),
+            DataValue::Str("abc".into())
+        ])
+        .unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_starts_with(&[DataValue::Str("abcdef".into()), DataValue::Str("bc".into())]).unwrap(),
+        DataValue::from(false)
+    );
+    assert_eq!(
+        op_ends_with(&[
+            DataValue::Str("abcdef".into()),
+            DataValue::Str("def".into())
+        ])
+        .unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_ends_with(&[DataValue::Str("abcdef".into()), DataValue::Str("bc".into())]).unwrap(),
+        DataValue::from(false)
+    );
+}
+
+#[test]
+fn test_regex() {
+    assert_eq!(
+        op_regex_matches(&[
+            DataValue::Str("abcdef".into()),
+            DataValue::Regex(RegexWrapper(Regex::new("c.e").unwrap()))
         ])
         .unwrap(),
         DataValue::from(true)
@@ -791,6 +1567,36 @@ fn test_regex() {
     );
 }
 
+#[test]
+fn test_string_replace_regex() {
+    assert_eq!(get_op("string_replace_regex"), Some(&OP_REGEX_REPLACE_ALL));
+    let db = new_cozo_mem().unwrap();
+    let replace = |pattern: &str, replacement: &str| {
+        db.run_script(
+            &format!(r#"?[x] := x = string_replace_regex('2023-01-31 2024-12-25', "{pattern}", "{replacement}")"#),
+            Default::default(),
+        )
+        .map(|res| res.rows[0][0].clone())
+    };
+    assert_eq!(
+        replace("-", "/").unwrap(),
+        DataValue::from("2023/01/31 2024/12/25")
+    );
+    assert_eq!(
+        replace(r"(\d+)-(\d+)-(\d+)", "$3.$2.$1").unwrap(),
+        DataValue::from("31.01.2023 25.12.2024")
+    );
+    assert_eq!(
+        replace(r"(?P<y>\d+)-(?P<m>\d+)-(?P<d>\d+)", "${m}/${d}").unwrap(),
+        DataValue::from("01/31 12/25")
+    );
+    assert_eq!(
+        replace(r"\d{4}", "$$$0").unwrap(),
+        DataValue::from("$2023-01-31 $2024-12-25")
+    );
+    assert!(replace("(unclosed", "x").is_err());
+}
+
 #[test]
 fn test_predicates() {
     assert_eq!(
@@ -801,6 +1607,18 @@ fn test_predicates() {
         op_is_null(&[DataValue::Bot]).unwrap(),
         DataValue::from(false)
     );
+    assert_eq!(
+        op_is_null(&[DataValue::from(0)]).unwrap(),
+        DataValue::from(false)
+    );
+    assert_eq!(
+        op_is_not_null(&[DataValue::from("a")]).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_is_not_null(&[DataValue::Null]).unwrap(),
+        DataValue::from(false)
+    );
     assert_eq!(
         op_is_int(&[DataValue::from(1)]).unwrap(),
         DataValue::from(true)
@@ -899,6 +1717,66 @@ fn test_predicates() {
     );
 }
 
+#[test]
+fn test_typeof() {
+    for (val, expected) in [
+        (DataValue::Null, "null"),
+        (DataValue::from(false), "bool"),
+        (DataValue::from(1), "int"),
+        (DataValue::from(1.0), "float"),
+        (DataValue::from(""), "string"),
+        (DataValue::Bytes(vec![1, 2]), "bytes"),
+        (op_rand_uuid_v4(&[]).unwrap(), "uuid"),
+        (
+            DataValue::Regex(RegexWrapper(Regex::new("a").unwrap())),
+            "regex",
+        ),
+        (DataValue::List(vec![]), "list"),
+        (
+            DataValue::List(vec![DataValue::List(vec![DataValue::from(1)])]),
+            "list",
+        ),
+    ] {
+        assert_eq!(op_typeof(&[val]).unwrap(), DataValue::from(expected));
+    }
+}
+
+#[test]
+fn test_hash() {
+    let hash = |v: DataValue| op_hash(&[v]).unwrap();
+    let list = |xs: &[i64]| DataValue::List(xs.iter().map(|x| DataValue::from(*x)).collect());
+
+    // pinned, as the results must not change between runs, versions or platforms
+    assert_eq!(hash(DataValue::Null), DataValue::from(-5808592057526012372));
+    assert_eq!(hash(DataValue::from("abc")), hash(DataValue::from("abc")));
+    assert_eq!(
+        hash(DataValue::List(vec![list(&[1, 2]), DataValue::from("x")])),
+        hash(DataValue::List(vec![list(&[1, 2]), DataValue::from("x")]))
+    );
+    assert_ne!(hash(list(&[1, 2])), hash(list(&[2, 1])));
+    assert_ne!(hash(list(&[1, 2])), hash(list(&[1, 2, 0])));
+    assert_ne!(
+        hash(DataValue::List(vec![list(&[1]), list(&[2])])),
+        hash(DataValue::List(vec![list(&[1, 2])]))
+    );
+    assert_ne!(hash(DataValue::from("1")), hash(DataValue::from(1)));
+    // values equal under `==` hash alike
+    assert_eq!(hash(DataValue::from(1)), hash(DataValue::from(1.0)));
+    assert_eq!(hash(DataValue::from(0.0)), hash(DataValue::from(-0.0)));
+    assert_eq!(
+        hash(list(&[1, 2])),
+        hash(DataValue::List(vec![
+            DataValue::from(1.0),
+            DataValue::from(2.0)
+        ]))
+    );
+    assert_ne!(hash(DataValue::from(1)), hash(DataValue::from(1.5)));
+    assert!(matches!(
+        hash(DataValue::from(1.5)),
+        DataValue::Num(Num::Int(_))
+    ));
+}
+
 #[test]
 fn test_prepend_append() {
     assert_eq!(
@@ -941,6 +1819,54 @@ fn test_length() {
         op_length(&[DataValue::Bytes([].into())]).unwrap(),
         DataValue::from(0)
     );
+    assert_eq!(
+        op_length(&[DataValue::Set([DataValue::Null].into())]).unwrap(),
+        DataValue::from(1)
+    );
+    assert!(op_length(&[DataValue::from(1)]).is_err());
+    assert!(op_length(&[DataValue::Null]).is_err());
+    assert_eq!(get_op("len"), Some(&OP_LENGTH));
+}
+
+#[test]
+fn test_has() {
+    let list = DataValue::List(vec![DataValue::from(1), DataValue::Null]);
+    assert_eq!(
+        op_has(&[list.clone(), DataValue::from(1)]).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_has(&[list.clone(), DataValue::Null]).unwrap(),
+        DataValue::from(true)
+    );
+    // numbers compare across types, as in `in`
+    assert_eq!(
+        op_has(&[list.clone(), DataValue::from(1.0)]).unwrap(),
+        op_in(&[DataValue::from(1.0), list]).unwrap()
+    );
+    let nested = DataValue::List(vec![DataValue::List(vec![DataValue::from(2)])]);
+    assert_eq!(
+        op_has(&[nested, DataValue::List(vec![DataValue::from(2.0)])]).unwrap(),
+        DataValue::from(true)
+    );
+    let set = DataValue::Set([DataValue::from("a"), DataValue::from(3)].into());
+    assert_eq!(
+        op_has(&[set.clone(), DataValue::from(3.0)]).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_has(&[set.clone(), DataValue::from("a")]).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_has(&[set, DataValue::from("b")]).unwrap(),
+        DataValue::from(false)
+    );
+    let err = op_has(&[DataValue::from("abc"), DataValue::from("a")]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "'has' requires lists, sets or maps for argument 1"
+    );
 }
 
 #[test]
@@ -983,7 +1909,164 @@ fn test_sort_reverse() {
             DataValue::from(1),
             DataValue::from(2.0),
         ])
-    )
+    );
+    assert_eq!(
+        op_reverse(&[DataValue::from("abc")]).unwrap(),
+        DataValue::from("cba")
+    );
+    assert_eq!(
+        op_reverse(&[DataValue::from("日本語")]).unwrap(),
+        DataValue::from("語本日")
+    );
+    assert_eq!(
+        op_reverse(&[DataValue::from("e\u{301}x")]).unwrap(),
+        DataValue::from("x\u{301}e")
+    );
+    assert!(op_reverse(&[DataValue::from(1)]).is_err());
+    assert_eq!(
+        op_sorted(&[DataValue::List(vec![
+            DataValue::from("b"),
+            DataValue::from("c"),
+            DataValue::from("a"),
+        ])])
+        .unwrap(),
+        DataValue::List(vec![
+            DataValue::from("a"),
+            DataValue::from("b"),
+            DataValue::from("c"),
+        ])
+    );
+    let mixed = DataValue::List(vec![
+        DataValue::List(vec![]),
+        DataValue::from("a"),
+        DataValue::from(1),
+        DataValue::from(true),
+        DataValue::Null,
+    ]);
+    assert_eq!(
+        op_sorted(&[mixed.clone()]).unwrap(),
+        DataValue::List(vec![
+            DataValue::Null,
+            DataValue::from(true),
+            DataValue::from(1),
+            DataValue::from("a"),
+            DataValue::List(vec![]),
+        ])
+    );
+    assert_eq!(
+        op_sort_desc(&[mixed]).unwrap(),
+        DataValue::List(vec![
+            DataValue::List(vec![]),
+            DataValue::from("a"),
+            DataValue::from(1),
+            DataValue::from(true),
+            DataValue::Null,
+        ])
+    );
+    assert!(op_sort_desc(&[DataValue::from("abc")]).is_err());
+}
+
+#[test]
+fn test_distinct() {
+    assert_eq!(
+        op_distinct(&[DataValue::List(vec![
+            DataValue::from(3),
+            DataValue::from(1),
+            DataValue::from(3),
+            DataValue::from(2),
+            DataValue::from(1.0),
+        ])])
+        .unwrap(),
+        DataValue::List(vec![
+            DataValue::from(3),
+            DataValue::from(1),
+            DataValue::from(2),
+        ])
+    );
+    assert_eq!(
+        op_distinct(&[DataValue::List(vec![])]).unwrap(),
+        DataValue::List(vec![])
+    );
+    // `0.0 == -0.0` holds for `==`, while NaN equals nothing, not even itself
+    assert_eq!(
+        op_distinct(&[DataValue::List(vec![
+            DataValue::from(0.0),
+            DataValue::from(-0.0),
+            DataValue::from(f64::NAN),
+            DataValue::from(f64::NAN),
+        ])])
+        .unwrap(),
+        DataValue::List(vec![
+            DataValue::from(0.0),
+            DataValue::from(f64::NAN),
+            DataValue::from(f64::NAN)
+        ])
+    );
+    assert_eq!(
+        op_distinct(&[DataValue::List(vec![
+            DataValue::List(vec![DataValue::from(1), DataValue::from(2)]),
+            DataValue::List(vec![DataValue::from(1.0), DataValue::from(2)]),
+            DataValue::List(vec![DataValue::from(f64::NAN)]),
+            DataValue::List(vec![DataValue::from(f64::NAN)]),
+        ])])
+        .unwrap(),
+        DataValue::List(vec![
+            DataValue::List(vec![DataValue::from(1), DataValue::from(2)]),
+            DataValue::List(vec![DataValue::from(f64::NAN)]),
+            DataValue::List(vec![DataValue::from(f64::NAN)]),
+        ])
+    );
+    // long lists are not compared pairwise
+    let long = DataValue::List((0..200_000).map(|i| DataValue::from(i % 1000)).collect());
+    assert_eq!(
+        op_distinct(&[long]).unwrap(),
+        DataValue::List((0..1000).map(DataValue::from).collect())
+    );
+    assert!(op_distinct(&[DataValue::from("aab")]).is_err());
+}
+
+#[test]
+fn test_list_map() {
+    assert_eq!(
+        op_list_map(&[
+            DataValue::List(vec![
+                DataValue::from(1),
+                DataValue::from(-2),
+                DataValue::from(3.5),
+            ]),
+            DataValue::from("abs")
+        ])
+        .unwrap(),
+        DataValue::List(vec![
+            DataValue::from(1),
+            DataValue::from(2),
+            DataValue::from(3.5),
+        ])
+    );
+    assert_eq!(
+        op_list_map(&[DataValue::List(vec![]), DataValue::from("abs")]).unwrap(),
+        DataValue::List(vec![])
+    );
+    assert!(op_list_map(&[
+        DataValue::List(vec![DataValue::from(1)]),
+        DataValue::from("no_such_function")
+    ])
+    .is_err());
+    assert!(op_list_map(&[
+        DataValue::List(vec![DataValue::from(1)]),
+        DataValue::from("atan2")
+    ])
+    .is_err());
+    assert!(op_list_map(&[
+        DataValue::List(vec![DataValue::from("a")]),
+        DataValue::from("abs")
+    ])
+    .is_err());
+    assert!(op_list_map(&[
+        DataValue::List(vec![DataValue::from(0.5)]),
+        DataValue::from("rand_bernoulli")
+    ])
+    .is_err());
 }
 
 #[test]
@@ -1060,6 +2143,22 @@ fn test_first_last() {
         .unwrap(),
         DataValue::from(2),
     );
+    assert_eq!(
+        op_rest(&[DataValue::List(vec![])]).unwrap(),
+        DataValue::List(vec![]),
+    );
+    assert_eq!(
+        op_rest(&[DataValue::List(vec![
+            DataValue::from(1),
+            DataValue::from(2),
+            DataValue::from(3),
+        ])])
+        .unwrap(),
+        DataValue::List(vec![DataValue::from(2), DataValue::from(3)]),
+    );
+    assert!(op_first(&[DataValue::from("abc")]).is_err());
+    assert!(op_last(&[DataValue::from(1)]).is_err());
+    assert!(op_rest(&[DataValue::Null]).is_err());
 }
 
 #[test]
@@ -1131,6 +2230,117 @@ fn test_chunks() {
     )
 }
 
+#[test]
+fn test_chunk_sizes() {
+    let list = |items: &[i64]| DataValue::List(items.iter().map(|i| DataValue::from(*i)).collect());
+    assert_eq!(
+        op_chunks(&[list(&[1, 2, 3, 4]), DataValue::from(2)]).unwrap(),
+        DataValue::List(vec![list(&[1, 2]), list(&[3, 4])])
+    );
+    assert_eq!(
+        op_chunks(&[list(&[1, 2]), DataValue::from(5)]).unwrap(),
+        DataValue::List(vec![list(&[1, 2])])
+    );
+    assert_eq!(
+        op_chunks(&[list(&[]), DataValue::from(2)]).unwrap(),
+        DataValue::List(vec![])
+    );
+    assert!(op_chunks(&[list(&[1, 2]), DataValue::from(0)]).is_err());
+    assert!(op_chunks(&[list(&[1, 2]), DataValue::from(-1)]).is_err());
+    assert_eq!(get_op("chunk"), Some(&OP_CHUNKS));
+}
+
+#[test]
+fn test_take_drop() {
+    let list = |items: &[i64]| DataValue::List(items.iter().map(|i| DataValue::from(*i)).collect());
+    let abc = list(&[1, 2, 3]);
+    assert_eq!(
+        op_take(&[abc.clone(), DataValue::from(2)]).unwrap(),
+        list(&[1, 2])
+    );
+    assert_eq!(
+        op_drop(&[abc.clone(), DataValue::from(2)]).unwrap(),
+        list(&[3])
+    );
+    assert_eq!(
+        op_take(&[abc.clone(), DataValue::from(0)]).unwrap(),
+        list(&[])
+    );
+    assert_eq!(
+        op_drop(&[abc.clone(), DataValue::from(0)]).unwrap(),
+        abc.clone()
+    );
+    // counts beyond the end are clamped
+    assert_eq!(
+        op_take(&[abc.clone(), DataValue::from(10)]).unwrap(),
+        abc.clone()
+    );
+    assert_eq!(
+        op_drop(&[abc.clone(), DataValue::from(10)]).unwrap(),
+        list(&[])
+    );
+    assert_eq!(
+        op_take(&[list(&[]), DataValue::from(1)]).unwrap(),
+        list(&[])
+    );
+    assert_eq!(
+        op_drop(&[list(&[]), DataValue::from(1)]).unwrap(),
+        list(&[])
+    );
+    assert!(op_take(&[abc.clone(), DataValue::from(-1)]).is_err());
+    assert!(op_drop(&[abc, DataValue::from(-1)]).is_err());
+    assert!(op_take(&[DataValue::from("abc"), DataValue::from(1)]).is_err());
+}
+
+#[test]
+fn test_zip() {
+    let list = |items: &[i64]| DataValue::List(items.iter().map(|i| DataValue::from(*i)).collect());
+    assert_eq!(
+        op_zip(&[list(&[1, 2]), list(&[3, 4])]).unwrap(),
+        DataValue::List(vec![list(&[1, 3]), list(&[2, 4])])
+    );
+    assert_eq!(
+        op_zip(&[list(&[1, 2, 3]), list(&[4])]).unwrap(),
+        DataValue::List(vec![list(&[1, 4])])
+    );
+    assert_eq!(
+        op_zip(&[list(&[1]), list(&[4, 5, 6])]).unwrap(),
+        DataValue::List(vec![list(&[1, 4])])
+    );
+    assert_eq!(
+        op_zip(&[list(&[]), list(&[1])]).unwrap(),
+        DataValue::List(vec![])
+    );
+    assert_eq!(
+        op_zip(&[list(&[]), list(&[])]).unwrap(),
+        DataValue::List(vec![])
+    );
+    assert!(op_zip(&[list(&[1]), DataValue::from("a")]).is_err());
+}
+
+#[test]
+fn test_flatten() {
+    let list = |items: &[i64]| DataValue::List(items.iter().map(|i| DataValue::from(*i)).collect());
+    assert_eq!(
+        op_flatten(&[DataValue::List(vec![list(&[1, 2]), list(&[3])])]).unwrap(),
+        list(&[1, 2, 3])
+    );
+    assert_eq!(
+        op_flatten(&[DataValue::List(vec![list(&[]), list(&[1])])]).unwrap(),
+        list(&[1])
+    );
+    assert_eq!(op_flatten(&[list(&[])]).unwrap(), list(&[]));
+    // only one level is removed
+    let nested = DataValue::List(vec![DataValue::List(vec![list(&[1]), list(&[2])])]);
+    assert_eq!(
+        op_flatten(&[nested]).unwrap(),
+        DataValue::List(vec![list(&[1]), list(&[2])])
+    );
+    // non-list elements are an error rather than being kept in place
+    assert!(op_flatten(&[DataValue::List(vec![list(&[1]), DataValue::from(2)])]).is_err());
+    assert!(op_flatten(&[DataValue::from(1)]).is_err());
+}
+
 #[test]
 fn test_get() {
     assert!(op_get(&[DataValue::List(vec![]), DataValue::from(0)]).is_err());
@@ -1162,6 +2372,31 @@ fn test_get() {
         .unwrap(),
         DataValue::from(2)
     );
+    let l = DataValue::List(vec![
+        DataValue::from(10),
+        DataValue::from(20),
+        DataValue::from(30),
+    ]);
+    assert_eq!(
+        op_get(&[l.clone(), DataValue::from(-1)]).unwrap(),
+        DataValue::from(30)
+    );
+    assert_eq!(
+        op_maybe_get(&[l.clone(), DataValue::from(-3)]).unwrap(),
+        DataValue::from(10)
+    );
+    assert!(op_get(&[l.clone(), DataValue::from(3)]).is_err());
+    assert_eq!(
+        op_maybe_get(&[l.clone(), DataValue::from(3)]).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(
+        op_maybe_get(&[l.clone(), DataValue::from(-4)]).unwrap(),
+        DataValue::Null
+    );
+    assert!(op_get(&[l.clone(), DataValue::from(0.5)]).is_err());
+    assert!(op_maybe_get(&[l, DataValue::from("0")]).is_err());
+    assert!(op_get(&[DataValue::from("abc"), DataValue::from(0)]).is_err());
 }
 
 #[test]
@@ -1192,12 +2427,54 @@ fn test_slice() {
     );
 }
 
+#[test]
+fn test_sublist() {
+    let l = DataValue::List(vec![
+        DataValue::from(1),
+        DataValue::from(2),
+        DataValue::from(3),
+    ]);
+    assert_eq!(
+        op_sublist(&[l.clone(), DataValue::from(-2), DataValue::Null]).unwrap(),
+        DataValue::List(vec![DataValue::from(2), DataValue::from(3)])
+    );
+    assert_eq!(
+        op_sublist(&[l.clone(), DataValue::Null, DataValue::from(100)]).unwrap(),
+        l
+    );
+    assert!(op_sublist(&[l.clone(), DataValue::from("a"), DataValue::Null]).is_err());
+    assert!(op_sublist(&[DataValue::from("abc"), DataValue::Null, DataValue::Null]).is_err());
+}
+
 #[test]
 fn test_chars() {
     assert_eq!(
         op_from_substrings(&[op_chars(&[DataValue::Str("abc".into())]).unwrap()]).unwrap(),
         DataValue::Str("abc".into())
-    )
+    );
+    let chars = op_chars(&[DataValue::from("aé😀")]).unwrap();
+    assert_eq!(
+        chars,
+        DataValue::List(vec![
+            DataValue::from("a"),
+            DataValue::from("é"),
+            DataValue::from("😀")
+        ])
+    );
+    assert_eq!(op_from_chars(&[chars]).unwrap(), DataValue::from("aé😀"));
+    assert_eq!(
+        op_from_chars(&[DataValue::List(vec![])]).unwrap(),
+        DataValue::from("")
+    );
+    for bad in [
+        DataValue::from("ab"),
+        DataValue::from(""),
+        DataValue::from(1),
+    ] {
+        assert!(op_from_chars(&[DataValue::List(vec![DataValue::from("a"), bad])]).is_err());
+    }
+    assert!(op_from_chars(&[DataValue::from("a")]).is_err());
+    assert_eq!(get_op("to_chars"), Some(&OP_CHARS));
 }
 
 #[test]
@@ -1215,6 +2492,90 @@ fn test_to_string() {
         op_to_string(&[DataValue::from(false)]).unwrap(),
         DataValue::Str("false".into())
     );
+    for (f, expected) in [
+        (1.0, "1.0"),
+        (-0.5, "-0.5"),
+        (0.0001, "0.0001"),
+        (0.00001, "1e-5"),
+        (1e15, "1000000000000000.0"),
+        (1e16, "1e16"),
+        (1.5e300, "1.5e300"),
+        (f64::NAN, "NaN"),
+        (f64::NEG_INFINITY, "-inf"),
+    ] {
+        assert_eq!(
+            op_to_string(&[DataValue::from(f)]).unwrap(),
+            DataValue::from(expected)
+        );
+    }
+    // formatting templates stringify in the same way
+    assert_eq!(
+        op_format(&[DataValue::from("{}"), DataValue::from(2.0)]).unwrap(),
+        DataValue::from("2.0")
+    );
+}
+
+#[test]
+fn test_format_float() {
+    assert_eq!(
+        op_format_float(&[DataValue::from(1.23456), DataValue::from(2)]).unwrap(),
+        DataValue::from("1.23")
+    );
+    assert_eq!(
+        op_format_float(&[DataValue::from(2.5), DataValue::from(0)]).unwrap(),
+        DataValue::from("2")
+    );
+    assert_eq!(
+        op_format_float(&[DataValue::from(1), DataValue::from(3)]).unwrap(),
+        DataValue::from("1.000")
+    );
+    assert_eq!(
+        op_format_float(&[DataValue::from(1e20), DataValue::from(1)]).unwrap(),
+        DataValue::from("100000000000000000000.0")
+    );
+    assert!(op_format_float(&[DataValue::from(1.0), DataValue::from(-1)]).is_err());
+    let tiny =
+        op_format_float(&[DataValue::from(f64::from_bits(1)), DataValue::from(1074)]).unwrap();
+    assert!(tiny.get_str().unwrap().ends_with("3447265625"));
+    assert!(op_format_float(&[DataValue::from(1.0), DataValue::from(1075)]).is_err());
+    assert!(op_format_float(&[DataValue::from(1.0), DataValue::from(1_000_000_000)]).is_err());
+    assert!(op_format_float(&[DataValue::from("1"), DataValue::from(1)]).is_err());
+}
+
+#[test]
+fn test_format() {
+    assert_eq!(
+        op_format(&[
+            DataValue::from("{} + {} = {}"),
+            DataValue::from(1),
+            DataValue::from(2.5),
+            DataValue::from(3.5)
+        ])
+        .unwrap(),
+        DataValue::from("1 + 2.5 = 3.5")
+    );
+    assert_eq!(
+        op_format(&[
+            DataValue::from("{}: {}"),
+            DataValue::from("a"),
+            DataValue::List(vec![DataValue::from(1), DataValue::Null])
+        ])
+        .unwrap(),
+        DataValue::from("a: [1,null]")
+    );
+    assert_eq!(
+        op_format(&[DataValue::from("{{{}}} }}{{"), DataValue::from(true)]).unwrap(),
+        DataValue::from("{true} }{")
+    );
+    assert!(op_format(&[DataValue::from("{} {}"), DataValue::from(1)]).is_err());
+    assert!(op_format(&[
+        DataValue::from("{}"),
+        DataValue::from(1),
+        DataValue::from(2)
+    ])
+    .is_err());
+    assert!(op_format(&[DataValue::from("{"), DataValue::from(1)]).is_err());
+    assert!(op_format(&[DataValue::from(1)]).is_err());
 }
 
 #[test]
@@ -1258,8 +2619,55 @@ fn test_to_unity() {
     );
 }
 
+#[test]
+fn test_to_int() {
+    assert_eq!(
+        op_to_int(&[DataValue::from("42")]).unwrap(),
+        DataValue::from(42)
+    );
+    assert_eq!(
+        op_to_int(&[DataValue::from(3.9)]).unwrap(),
+        DataValue::from(3)
+    );
+    assert_eq!(
+        op_to_int(&[DataValue::from(-3.9)]).unwrap(),
+        DataValue::from(-3)
+    );
+    assert_eq!(
+        op_to_int(&[DataValue::from(true)]).unwrap(),
+        DataValue::from(1)
+    );
+    assert_eq!(op_to_int(&[DataValue::Null]).unwrap(), DataValue::from(0));
+    assert!(op_to_int(&[DataValue::from("abc")]).is_err());
+    assert!(op_to_int(&[DataValue::List(vec![])]).is_err());
+}
+
+#[test]
+fn test_parse_int_radix() {
+    let parse =
+        |s: &str, radix: i64| op_parse_int_radix(&[DataValue::from(s), DataValue::from(radix)]);
+    assert_eq!(parse("ff", 16).unwrap(), DataValue::from(255));
+    assert_eq!(parse("FF", 16).unwrap(), DataValue::from(255));
+    assert_eq!(parse("-101", 2).unwrap(), DataValue::from(-5));
+    assert_eq!(parse("z", 36).unwrap(), DataValue::from(35));
+    assert_eq!(parse("777", 8).unwrap(), DataValue::from(511));
+    assert!(parse("12", 2).is_err());
+    assert!(parse("", 10).is_err());
+    assert!(parse("0x10", 16).is_err());
+    assert!(parse("8000000000000000", 16).is_err());
+    for radix in [0, 1, 37, -16] {
+        assert!(parse("1", radix).is_err());
+    }
+    assert!(op_parse_int_radix(&[DataValue::from(1), DataValue::from(10)]).is_err());
+}
+
 #[test]
 fn test_to_float() {
+    assert_eq!(
+        op_to_float(&[DataValue::from("1.5")]).unwrap(),
+        DataValue::from(1.5)
+    );
+    assert!(op_to_float(&[DataValue::from("abc")]).is_err());
     assert_eq!(
         op_to_float(&[DataValue::Null]).unwrap(),
         DataValue::from(0.0)
@@ -1323,7 +2731,25 @@ fn test_rand() {
         .get_int()
         .unwrap();
     assert!(n >= 100);
-    assert!(n <= 200);
+    assert!(n < 200);
+    for _ in 0..1000 {
+        let f = op_rand_float(&[]).unwrap().get_float().unwrap();
+        assert!((0. ..1.).contains(&f));
+        let i = op_rand_int(&[DataValue::from(-2), DataValue::from(2)])
+            .unwrap()
+            .get_int()
+            .unwrap();
+        assert!((-2..2).contains(&i));
+    }
+    // the upper bound is exclusive
+    assert_eq!(
+        op_rand_int(&[DataValue::from(7), DataValue::from(8)]).unwrap(),
+        DataValue::from(7)
+    );
+    assert!(op_rand_int(&[DataValue::from(3), DataValue::from(3)]).is_err());
+    assert!(op_rand_int(&[DataValue::from(4), DataValue::from(3)]).is_err());
+    assert!(get_op("rand").unwrap().has_side_effect());
+    assert!(get_op("rand_int").unwrap().has_side_effect());
     assert_eq!(
         op_rand_choose(&[DataValue::List(vec![])]).unwrap(),
         DataValue::Null
@@ -1385,6 +2811,37 @@ fn test_uuid() {
     assert!(op_to_uuid(&[DataValue::from("f3b4958c-52a1-11e7-802a-010203040506")]).is_ok());
 }
 
+#[test]
+fn test_uuid_round_trip() {
+    let a = op_rand_uuid_v4(&[]).unwrap();
+    let b = op_rand_uuid_v4(&[]).unwrap();
+    assert_ne!(a, b);
+    let s = op_to_string(&[a.clone()]).unwrap();
+    assert_eq!(s.get_str().unwrap().len(), 36);
+    assert_eq!(op_to_uuid(&[s]).unwrap(), a);
+    assert!(get_op("uuid").unwrap().has_side_effect());
+    assert!(!get_op("parse_uuid").unwrap().has_side_effect());
+
+    // ordered by time fields first, so v1 UUIDs sort by their timestamps
+    let early = op_to_uuid(&[DataValue::from("f3b4958c-52a1-11e7-802a-010203040506")]).unwrap();
+    let late = op_to_uuid(&[DataValue::from("0000958c-52a2-11e7-802a-010203040506")]).unwrap();
+    assert_eq!(
+        op_lt(&[early.clone(), late.clone()]).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_eq(&[early.clone(), early]).unwrap(),
+        DataValue::from(true)
+    );
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(r#"?[a, b] := a = uuid(), b = uuid()"#, Default::default())
+        .unwrap()
+        .rows;
+    assert_ne!(res[0][0], res[0][1]);
+}
+
 #[test]
 fn test_now() {
     let now = op_now(&[]).unwrap();
@@ -1393,6 +2850,135 @@ fn test_now() {
     let _dt = op_parse_timestamp(&[s]).unwrap();
 }
 
+#[test]
+fn test_timestamp_round_trip() {
+    for s in [
+        "2022-11-28T13:45:10.500+00:00",
+        "1969-07-20T20:17:40+00:00",
+        "1970-01-01T00:00:00+00:00",
+    ] {
+        let ts = op_parse_timestamp(&[DataValue::from(s)]).unwrap();
+        assert_eq!(op_format_timestamp(&[ts]).unwrap(), DataValue::from(s));
+    }
+    assert_eq!(
+        op_parse_timestamp(&[DataValue::from("1969-12-31T23:59:59.5+00:00")]).unwrap(),
+        DataValue::from(-0.5)
+    );
+    let ts = op_parse_timestamp(&[DataValue::from("2022-11-28T21:45:10+08:00")]).unwrap();
+    assert_eq!(
+        op_format_timestamp(&[ts, DataValue::from("Asia/Shanghai")]).unwrap(),
+        DataValue::from("2022-11-28T21:45:10+08:00")
+    );
+    assert!(op_parse_timestamp(&[DataValue::from("yesterday")]).is_err());
+}
+
+#[test]
+fn test_timestamp_arithmetic() {
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            r#"
+            ?[later, elapsed, before] :=
+                t = parse_timestamp("2022-11-28T13:45:10+00:00"),
+                later = format_timestamp(t + 3600),
+                elapsed = parse_timestamp("2022-11-29T13:45:10+00:00") - t,
+                before = t - 60 < t
+            "#,
+            Default::default(),
+        )
+        .unwrap()
+        .rows;
+    assert_eq!(
+        res[0],
+        vec![
+            DataValue::from("2022-11-28T14:45:10+00:00"),
+            DataValue::from(86400.),
+            DataValue::from(true)
+        ]
+    );
+
+    let a = op_now(&[]).unwrap();
+    let b = op_now(&[]).unwrap();
+    assert_eq!(op_le(&[a, b]).unwrap(), DataValue::from(true));
+}
+
+#[test]
+fn test_hex() {
+    let b = DataValue::Bytes(vec![0x00, 0x7f, 0xab, 0xff]);
+    let s = op_to_hex(&[b.clone()]).unwrap();
+    assert_eq!(s, DataValue::from("007fabff"));
+    assert_eq!(op_from_hex(&[s]).unwrap(), b);
+    assert_eq!(op_from_hex(&[DataValue::from("007FABFF")]).unwrap(), b);
+    assert_eq!(
+        op_from_hex(&[DataValue::from("")]).unwrap(),
+        DataValue::Bytes(vec![])
+    );
+    assert!(op_from_hex(&[DataValue::from("abc")]).is_err());
+    assert!(op_from_hex(&[DataValue::from("zz")]).is_err());
+    assert!(op_from_hex(&[DataValue::from("é0")]).is_err());
+    assert!(op_to_hex(&[DataValue::from("ab")]).is_err());
+}
+
+#[test]
+fn test_base64_padding() {
+    for (raw, encoded) in [
+        (&b""[..], ""),
+        (&b"f"[..], "Zg=="),
+        (&b"fo"[..], "Zm8="),
+        (&b"foo"[..], "Zm9v"),
+        (&b"foob"[..], "Zm9vYg=="),
+    ] {
+        let b = DataValue::Bytes(raw.to_vec());
+        assert_eq!(
+            op_encode_base64(&[b.clone()]).unwrap(),
+            DataValue::from(encoded)
+        );
+        assert_eq!(op_decode_base64(&[DataValue::from(encoded)]).unwrap(), b);
+    }
+    assert!(op_decode_base64(&[DataValue::from("Zg")]).is_err());
+    assert!(op_decode_base64(&[DataValue::from("Zg=")]).is_err());
+}
+
+#[test]
+fn test_bytes_literals() {
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            r#"
+            ?[a, b, c, d, e] :=
+                a = hex"cafe",
+                b = b64"yv4=",
+                c = a == b,
+                d = length(hex""),
+                e = to_base64(from_hex(to_hex(a)))
+            "#,
+            Default::default(),
+        )
+        .unwrap()
+        .rows;
+    assert_eq!(
+        res[0],
+        vec![
+            DataValue::Bytes(vec![0xca, 0xfe]),
+            DataValue::Bytes(vec![0xca, 0xfe]),
+            DataValue::from(true),
+            DataValue::from(0),
+            DataValue::from("yv4=")
+        ]
+    );
+    assert!(db
+        .run_script(r#"?[a] := a = hex"abc""#, Default::default())
+        .is_err());
+    assert!(db
+        .run_script(r#"?[a] := a = b64"Zg=""#, Default::default())
+        .is_err());
+    let res = db
+        .run_script(r#"?[a] := a = hex"01" < hex"0100""#, Default::default())
+        .unwrap()
+        .rows;
+    assert_eq!(res[0][0], DataValue::from(true));
+}
+
 #[test]
 fn test_to_bool() {
     assert_eq!(
@@ -1439,7 +3025,28 @@ fn test_to_bool() {
 
 #[test]
 fn test_coalesce() {
+    assert_eq!(
+        op_coalesce(&[DataValue::Null, DataValue::Null, DataValue::from(3)]).unwrap(),
+        DataValue::from(3)
+    );
+    assert_eq!(
+        op_coalesce(&[DataValue::Null, DataValue::Null]).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(
+        op_coalesce(&[DataValue::from(false), DataValue::from(1)]).unwrap(),
+        DataValue::from(false)
+    );
+
     let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script("?[a] := a = coalesce(null, null, 3)", Default::default())
+        .unwrap()
+        .rows;
+    assert_eq!(res[0][0], DataValue::from(3));
+    assert!(db
+        .run_script("?[a] := a = coalesce()", Default::default())
+        .is_err());
     let res = db
         .run_script("?[a] := a = null ~ 1 ~ 2", Default::default())
         .unwrap()
@@ -1456,3 +3063,141 @@ fn test_coalesce() {
         .rows;
     assert_eq!(res[0][0], DataValue::from(2));
 }
+
+#[test]
+fn test_nullif() {
+    let nullif = |a: DataValue, b: DataValue| op_nullif(&[a, b]).unwrap();
+    assert_eq!(
+        nullif(DataValue::from(""), DataValue::from("")),
+        DataValue::Null
+    );
+    assert_eq!(
+        nullif(DataValue::from("a"), DataValue::from("")),
+        DataValue::from("a")
+    );
+    assert_eq!(
+        nullif(DataValue::from(5), DataValue::from(3)),
+        DataValue::from(5)
+    );
+    // compared as in `==`
+    assert_eq!(
+        nullif(DataValue::from(1), DataValue::from(1.0)),
+        DataValue::Null
+    );
+    assert_eq!(nullif(DataValue::Null, DataValue::from(1)), DataValue::Null);
+    assert_eq!(
+        nullif(DataValue::from(1), DataValue::Null),
+        DataValue::from(1)
+    );
+    assert_eq!(nullif(DataValue::Null, DataValue::Null), DataValue::Null);
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script("?[a] := a = nullif('', '') ~ 'n/a'", Default::default())
+        .unwrap()
+        .rows;
+    assert_eq!(res[0][0], DataValue::from("n/a"));
+    // keywords do not swallow the start of names
+    let res = db
+        .run_script(
+            "?[a, b] := nullable = 1, true_count = 2, a = nullif(nullable, true_count), b = null",
+            Default::default(),
+        )
+        .unwrap()
+        .rows;
+    assert_eq!(res[0], [DataValue::from(1), DataValue::Null]);
+}
+
+#[test]
+fn test_errors_escape_string_values() {
+    let s = DataValue::from("a\nb\u{7}");
+    let errs = [
+        op_lt(&[s.clone(), DataValue::from(1)]).unwrap_err(),
+        op_parse_timestamp(&[s.clone()]).unwrap_err(),
+        op_unicode_normalize(&[DataValue::from("x"), s.clone()]).unwrap_err(),
+        op_format_timestamp(&[DataValue::from(0), s.clone()]).unwrap_err(),
+        op_list_map(&[DataValue::List(vec![]), s.clone()]).unwrap_err(),
+    ];
+    for err in errs {
+        let msg = err.to_string();
+        assert!(msg.contains(r"a\nb\u{7}"), "{msg}");
+        assert!(!msg.contains('\n'), "{msg}");
+    }
+
+    let db = new_cozo_mem().unwrap();
+    let err = db
+        .run_script("?[x] := x = parse_timestamp('a\\nb')", Default::default())
+        .unwrap_err();
+    let rendered = format!("{err:?}");
+    assert!(!rendered.contains("a\nb"), "{rendered}");
+    assert!(rendered.contains(r"a\nb"), "{rendered}");
+}
+
+#[test]
+fn test_map() {
+    let m = op_map(&[
+        DataValue::from("b"),
+        DataValue::from(2),
+        DataValue::from("a"),
+        DataValue::from(1),
+    ])
+    .unwrap();
+    assert_eq!(
+        op_map_keys(&[m.clone()]).unwrap(),
+        DataValue::List(vec![DataValue::from("a"), DataValue::from("b")])
+    );
+    assert_eq!(
+        op_map_values(&[m.clone()]).unwrap(),
+        DataValue::List(vec![DataValue::from(1), DataValue::from(2)])
+    );
+    assert_eq!(
+        op_map_get(&[m.clone(), DataValue::from("a")]).unwrap(),
+        DataValue::from(1)
+    );
+    assert_eq!(
+        op_map_get(&[m.clone(), DataValue::from("z")]).unwrap(),
+        DataValue::Null
+    );
+    let put = op_map_put(&[m.clone(), DataValue::from("a"), DataValue::from(10)]).unwrap();
+    assert_eq!(
+        op_map_get(&[put, DataValue::from("a")]).unwrap(),
+        DataValue::from(10)
+    );
+    assert_eq!(
+        op_map_get(&[m.clone(), DataValue::from("a")]).unwrap(),
+        DataValue::from(1)
+    );
+    assert!(op_map(&[DataValue::from("a")]).is_err());
+    assert!(op_map_get(&[DataValue::List(vec![]), DataValue::from(0)]).is_err());
+
+    assert_eq!(
+        op_has(&[m.clone(), DataValue::from("b")]).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_has(&[m.clone(), DataValue::from(2)]).unwrap(),
+        DataValue::from(false)
+    );
+    assert_eq!(op_length(&[m.clone()]).unwrap(), DataValue::from(2));
+    assert_eq!(op_typeof(&[m.clone()]).unwrap(), DataValue::from("map"));
+    assert_eq!(op_to_bool(&[m]).unwrap(), DataValue::from(true));
+    assert_eq!(
+        op_to_bool(&[op_map(&[]).unwrap()]).unwrap(),
+        DataValue::from(false)
+    );
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            "?[a, b, c] := m = {'x': 1, 2: [3]}, a = map_get(m, 'x'), b = map_get(m, 2), c = map_keys(m)",
+            Default::default(),
+        )
+        .unwrap()
+        .rows;
+    assert_eq!(res[0][0], DataValue::from(1));
+    assert_eq!(res[0][1], DataValue::List(vec![DataValue::from(3)]));
+    assert_eq!(
+        res[0][2],
+        DataValue::List(vec![DataValue::from(2), DataValue::from("x")])
+    );
+}
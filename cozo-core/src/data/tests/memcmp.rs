@@ -135,3 +135,39 @@ fn encode_decode_datavalues() {
     assert!(remaining.is_empty());
     assert_eq!(decoded, v);
 }
+
+#[test]
+fn encode_decode_map() {
+    let map = |pairs: &[(i64, &str)]| {
+        DataValue::Map(
+            pairs
+                .iter()
+                .map(|(k, v)| (DataValue::from(*k), DataValue::from(*v)))
+                .collect(),
+        )
+    };
+    let values = vec![
+        map(&[]),
+        map(&[(1, "a")]),
+        map(&[(1, "a"), (2, "a")]),
+        map(&[(1, "b")]),
+        map(&[(2, "a")]),
+    ];
+    let encoded = values
+        .iter()
+        .map(|v| {
+            let mut encoder = vec![];
+            encoder.encode_datavalue(v);
+            let (decoded, remaining) = DataValue::decode_from_key(&encoder);
+            assert_eq!(&decoded, v);
+            assert!(remaining.is_empty());
+            encoder
+        })
+        .collect::<Vec<_>>();
+    let mut sorted = encoded.clone();
+    sorted.sort();
+    assert_eq!(sorted, encoded);
+    let mut sorted = values.clone();
+    sorted.sort();
+    assert_eq!(sorted, values);
+}
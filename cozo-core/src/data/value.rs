@@ -9,7 +9,7 @@
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
 use std::cmp::{Ordering, Reverse};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
 
@@ -148,10 +148,58 @@ pub enum DataValue {
     Set(BTreeSet<DataValue>),
     /// validity
     Validity(Validity),
+    /// map, ordered by its keys
+    Map(BTreeMap<DataValue, DataValue>),
     /// bottom type, used internally only
     Bot,
 }
 
+/// The kind of a value as far as it can be known without evaluating anything
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueType {
+    /// null
+    Null,
+    /// boolean
+    Bool,
+    /// integer
+    Int,
+    /// float
+    Float,
+    /// number, but whether integer or float is only known at runtime
+    Number,
+    /// string
+    String,
+    /// bytes
+    Bytes,
+    /// UUID
+    Uuid,
+    /// list, including the sets used internally
+    List,
+    /// validity
+    Validity,
+    /// map
+    Map,
+}
+
+impl ValueType {
+    /// The type of a concrete value, `None` for values used internally only
+    pub fn of(val: &DataValue) -> Option<Self> {
+        Some(match val {
+            DataValue::Null => ValueType::Null,
+            DataValue::Bool(_) => ValueType::Bool,
+            DataValue::Num(Num::Int(_)) => ValueType::Int,
+            DataValue::Num(Num::Float(_)) => ValueType::Float,
+            DataValue::Str(_) => ValueType::String,
+            DataValue::Bytes(_) => ValueType::Bytes,
+            DataValue::Uuid(_) => ValueType::Uuid,
+            DataValue::List(_) | DataValue::Set(_) => ValueType::List,
+            DataValue::Validity(_) => ValueType::Validity,
+            DataValue::Map(_) => ValueType::Map,
+            DataValue::Regex(_) | DataValue::Bot => return None,
+        })
+    }
+}
+
 impl From<i64> for DataValue {
     fn from(v: i64) -> Self {
         DataValue::Num(Num::Int(v))
@@ -316,6 +364,7 @@ impl Display for DataValue {
             }
             DataValue::List(ls) => f.debug_list().entries(ls).finish(),
             DataValue::Set(s) => f.debug_list().entries(s).finish(),
+            DataValue::Map(m) => f.debug_map().entries(m).finish(),
             DataValue::Bot => write!(f, "null"),
             DataValue::Validity(v) => f
                 .debug_struct("Validity")
@@ -48,7 +48,7 @@ use miette::{
 };
 use serde_json::json;
 
-pub use data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs};
+pub use data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs, ValueType};
 pub use fixed_rule::{FixedRule, FixedRuleInputRelation, FixedRulePayload};
 pub use runtime::db::Db;
 pub use runtime::db::NamedRows;
@@ -65,11 +65,13 @@ pub use storage::sqlite::{new_cozo_sqlite, SqliteStorage};
 pub use storage::tikv::{new_cozo_tikv, TiKvStorage};
 pub use storage::{Storage, StoreTx};
 
-pub use crate::data::expr::Expr;
+pub use crate::data::expr::{register_op, Expr};
+pub use crate::data::functions::{numeric_promotion, NumericPromotion};
 use crate::data::json::JsonValue;
 pub use crate::data::symb::Symbol;
 pub use crate::fixed_rule::SimpleFixedRule;
-pub use crate::parse::SourceSpan;
+pub use crate::parse::expr::{expr2str, parse_expr_structured, ParseDiagnostic};
+pub use crate::parse::{SourcePosition, SourceSpan};
 pub use crate::runtime::callback::CallbackOp;
 pub use crate::runtime::db::Poison;
 pub use crate::runtime::db::TransactionPayload;
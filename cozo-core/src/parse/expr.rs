@@ -6,23 +6,30 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::hash_map::DefaultHasher;
 use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+use std::iter;
+use std::mem;
 
 use itertools::Itertools;
 use lazy_static::lazy_static;
-use miette::{bail, ensure, Diagnostic, Result};
+use miette::{bail, ensure, miette, Diagnostic, IntoDiagnostic, Result};
 use pest::pratt_parser::{Op, PrattParser};
+use pest::Parser;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::data::expr::{get_op, Bytecode, Expr};
+use crate::data::expr::{get_op, Bytecode, Expr, Op as ExprOp};
 use crate::data::functions::{
-    OP_ADD, OP_AND, OP_COALESCE, OP_CONCAT, OP_DIV, OP_EQ, OP_GE, OP_GT, OP_LE, OP_LIST, OP_LT,
-    OP_MINUS, OP_MOD, OP_MUL, OP_NEGATE, OP_NEQ, OP_OR, OP_POW, OP_SUB,
+    op_decode_base64, op_from_hex, OP_ADD, OP_AND, OP_CMP, OP_COALESCE, OP_CONCAT, OP_DIV, OP_EQ,
+    OP_GE, OP_GT, OP_IN, OP_LE, OP_LIST, OP_LT, OP_MAP, OP_MAYBE_GET, OP_MINUS, OP_MOD, OP_MUL,
+    OP_NEGATE, OP_NEQ, OP_OR, OP_POW, OP_SUB, OP_SUBLIST,
 };
 use crate::data::symb::Symbol;
-use crate::data::value::DataValue;
-use crate::parse::{ExtractSpan, Pair, Rule, SourceSpan};
+use crate::data::value::{DataValue, Num, ValueType};
+use crate::parse::{CozoScriptParser, ExtractSpan, Pair, ParseError, Rule, SourceSpan};
 
 lazy_static! {
     static ref PRATT_PARSER: PrattParser<Rule> = {
@@ -34,17 +41,20 @@ lazy_static! {
             .op(Op::infix(Rule::op_gt, Left)
                 | Op::infix(Rule::op_lt, Left)
                 | Op::infix(Rule::op_ge, Left)
-                | Op::infix(Rule::op_le, Left))
+                | Op::infix(Rule::op_le, Left)
+                | Op::infix(Rule::op_cmp, Left)
+                | Op::infix(Rule::op_in, Left))
             .op(Op::infix(Rule::op_eq, Left) | Op::infix(Rule::op_ne, Left))
+            // below arithmetic, so that `1 + 2 ++ "x"` concatenates the sum
+            .op(Op::infix(Rule::op_concat, Left))
             .op(Op::infix(Rule::op_mod, Left))
-            .op(Op::infix(Rule::op_add, Left)
-                | Op::infix(Rule::op_sub, Left)
-                | Op::infix(Rule::op_concat, Left))
+            .op(Op::infix(Rule::op_add, Left) | Op::infix(Rule::op_sub, Left))
             .op(Op::infix(Rule::op_mul, Left) | Op::infix(Rule::op_div, Left))
             .op(Op::infix(Rule::op_pow, Right))
             .op(Op::infix(Rule::op_coalesce, Left))
             .op(Op::prefix(Rule::minus))
             .op(Op::prefix(Rule::negate))
+            .op(Op::postfix(Rule::index_access) | Op::postfix(Rule::slice_access))
     };
 }
 
@@ -54,6 +64,131 @@ lazy_static! {
 pub(crate) struct InvalidExpression(#[label] pub(crate) SourceSpan);
 
 pub(crate) fn expr2bytecode(expr: &Expr, collector: &mut Vec<Bytecode>) {
+    let mut occurrences = BTreeMap::new();
+    count_pure_applications(expr, &mut occurrences);
+    let mut slots = BTreeMap::new();
+    for (slot, (_, members)) in occurrences
+        .into_values()
+        .flatten()
+        .filter(|(_, members)| members.len() > 1)
+        .enumerate()
+    {
+        for member in members {
+            slots.insert(member as *const Expr, slot);
+        }
+    }
+    emit_bytecode(expr, collector, &slots);
+}
+
+/// Applications that are deterministic and without side effects, grouped by structural hash
+/// and then by structural equality, each group listing the nodes it occurs at
+type Occurrences<'a> = BTreeMap<u64, Vec<(&'a Expr, Vec<&'a Expr>)>>;
+
+/// Groups structurally equal pure applications in `expr`,
+/// returning whether `expr` is pure and its structural hash
+fn count_pure_applications<'a>(expr: &'a Expr, occurrences: &mut Occurrences<'a>) -> (bool, u64) {
+    let mut hasher = DefaultHasher::new();
+    let pure = match expr {
+        Expr::Binding { var, tuple_pos } => {
+            (0u8, var, tuple_pos).hash(&mut hasher);
+            true
+        }
+        Expr::Const { val, .. } => {
+            (1u8, val).hash(&mut hasher);
+            true
+        }
+        Expr::Apply { op, args, .. } => {
+            (2u8, op.name).hash(&mut hasher);
+            let mut pure = !op.has_side_effect() && op.is_deterministic();
+            for arg in args.iter() {
+                let (arg_pure, arg_hash) = count_pure_applications(arg, occurrences);
+                pure &= arg_pure;
+                arg_hash.hash(&mut hasher);
+            }
+            pure
+        }
+        Expr::Cond { clauses, .. } => {
+            3u8.hash(&mut hasher);
+            let mut pure = true;
+            for (cond, val) in clauses {
+                for e in [cond, val] {
+                    let (e_pure, e_hash) = count_pure_applications(e, occurrences);
+                    pure &= e_pure;
+                    e_hash.hash(&mut hasher);
+                }
+            }
+            pure
+        }
+    };
+    let hash = hasher.finish();
+    if pure && matches!(expr, Expr::Apply { .. }) {
+        let bucket = occurrences.entry(hash).or_default();
+        match bucket.iter_mut().find(|(e, _)| same_expr(e, expr)) {
+            Some((_, members)) => members.push(expr),
+            None => bucket.push((expr, vec![expr])),
+        }
+    }
+    (pure, hash)
+}
+
+/// Equality disregarding source spans
+fn same_expr(a: &Expr, b: &Expr) -> bool {
+    match (a, b) {
+        (
+            Expr::Binding {
+                var: v1,
+                tuple_pos: p1,
+            },
+            Expr::Binding {
+                var: v2,
+                tuple_pos: p2,
+            },
+        ) => v1 == v2 && p1 == p2,
+        (Expr::Const { val: v1, .. }, Expr::Const { val: v2, .. }) => v1 == v2,
+        (
+            Expr::Apply {
+                op: o1, args: a1, ..
+            },
+            Expr::Apply {
+                op: o2, args: a2, ..
+            },
+        ) => {
+            o1 == o2
+                && a1.len() == a2.len()
+                && a1.iter().zip(a2.iter()).all(|(x, y)| same_expr(x, y))
+        }
+        (Expr::Cond { clauses: c1, .. }, Expr::Cond { clauses: c2, .. }) => {
+            c1.len() == c2.len()
+                && c1
+                    .iter()
+                    .zip(c2.iter())
+                    .all(|((c1, v1), (c2, v2))| same_expr(c1, c2) && same_expr(v1, v2))
+        }
+        _ => false,
+    }
+}
+
+/// Memo slots of common subexpressions, keyed by the address of each of their occurrences
+type Slots = BTreeMap<*const Expr, usize>;
+
+fn emit_bytecode(expr: &Expr, collector: &mut Vec<Bytecode>, shared: &Slots) {
+    // common subexpressions are computed on first use, which may be in a branch not always taken,
+    // so every occurrence is prepared to fill the slot
+    if let Some(&slot) = shared.get(&(expr as *const Expr)) {
+        collector.push(Bytecode::Recall { slot, jump_to: 0 });
+        let recall_pos = collector.len() - 1;
+        emit_uncached_bytecode(expr, collector, shared);
+        collector.push(Bytecode::Memoize { slot });
+        collector[recall_pos] = Bytecode::Recall {
+            slot,
+            jump_to: collector.len(),
+        };
+    } else {
+        emit_uncached_bytecode(expr, collector, shared);
+    }
+}
+
+fn emit_uncached_bytecode(expr: &Expr, collector: &mut Vec<Bytecode>, shared: &Slots) {
     match expr {
         Expr::Binding { var, tuple_pos } => collector.push(Bytecode::Binding {
             var: var.clone(),
@@ -63,22 +198,52 @@ pub(crate) fn expr2bytecode(expr: &Expr, collector: &mut Vec<Bytecode>) {
             val: val.clone(),
             span: *span,
         }),
+        Expr::Apply { op, args, span } if **op == OP_AND || **op == OP_OR => {
+            let decisive = **op == OP_OR;
+            // +1, the accumulator
+            collector.push(Bytecode::Const {
+                val: DataValue::from(!decisive),
+                span: *span,
+            });
+            let mut decisive_jump_pos = vec![];
+            for arg in args.iter() {
+                // +1
+                emit_bytecode(arg, collector, shared);
+                // -1
+                collector.push(Bytecode::Logical {
+                    decisive,
+                    jump_to: 0,
+                    span: *span,
+                });
+                decisive_jump_pos.push(collector.len() - 1);
+            }
+            let total_len = collector.len();
+            for pos in decisive_jump_pos {
+                collector[pos] = Bytecode::Logical {
+                    decisive,
+                    jump_to: total_len,
+                    span: *span,
+                };
+            }
+        }
         Expr::Apply { op, args, span } => {
             let arity = args.len();
+            // arguments are evaluated in order, the first one is deepest in the stack
             for arg in args.iter() {
-                expr2bytecode(arg, collector);
+                emit_bytecode(arg, collector, shared);
             }
             collector.push(Bytecode::Apply {
                 op,
                 arity,
                 span: *span,
+                arg_spans: args.iter().map(|a| a.span()).collect(),
             })
         }
         Expr::Cond { clauses, span } => {
             let mut return_jump_pos = vec![];
             for (cond, val) in clauses {
                 // +1
-                expr2bytecode(cond, collector);
+                emit_bytecode(cond, collector, shared);
                 // -1
                 collector.push(Bytecode::JumpIfFalse {
                     jump_to: 0,
@@ -86,7 +251,7 @@ pub(crate) fn expr2bytecode(expr: &Expr, collector: &mut Vec<Bytecode>) {
                 });
                 let false_jump_amend_pos = collector.len() - 1;
                 // +1 in this branch
-                expr2bytecode(val, collector);
+                emit_bytecode(val, collector, shared);
                 collector.push(Bytecode::Goto {
                     jump_to: 0,
                     span: *span,
@@ -108,14 +273,367 @@ pub(crate) fn expr2bytecode(expr: &Expr, collector: &mut Vec<Bytecode>) {
     }
 }
 
+/// Binding power of infix operators, mirroring `PRATT_PARSER`, with right associativity flagged
+fn infix_precedence(op: &ExprOp) -> Option<(&'static str, u8, bool)> {
+    Some(match op.name {
+        "OP_OR" => ("||", 1, false),
+        "OP_AND" => ("&&", 2, false),
+        "OP_GT" => (">", 3, false),
+        "OP_LT" => ("<", 3, false),
+        "OP_GE" => (">=", 3, false),
+        "OP_LE" => ("<=", 3, false),
+        "OP_CMP" => ("<=>", 3, false),
+        "OP_IN" => ("in", 3, false),
+        "OP_EQ" => ("==", 4, false),
+        "OP_NEQ" => ("!=", 4, false),
+        "OP_CONCAT" => ("++", 5, false),
+        "OP_MOD" => ("%", 6, false),
+        "OP_ADD" => ("+", 7, false),
+        "OP_SUB" => ("-", 7, false),
+        "OP_MUL" => ("*", 8, false),
+        "OP_DIV" => ("/", 8, false),
+        "OP_POW" => ("^", 9, true),
+        "OP_COALESCE" => ("~", 10, false),
+        _ => return None,
+    })
+}
+
+const PREFIX_PRECEDENCE: u8 = 11;
+const COMPARISON_OPERAND_PRECEDENCE: u8 = 5;
+
+fn is_comparison(op: &ExprOp) -> bool {
+    matches!(
+        op.name,
+        "OP_EQ" | "OP_NEQ" | "OP_GT" | "OP_GE" | "OP_LT" | "OP_LE" | "OP_CMP"
+    )
+}
+
+/// Renders the expression as source that parses back to the same tree. Infinite and NaN floats
+/// have no literals, and come back as `to_float` applications giving the same values.
+pub fn expr2str(expr: &Expr) -> String {
+    let mut ret = String::new();
+    write_expr(expr, 0, &mut ret);
+    ret
+}
+
+fn write_expr(expr: &Expr, min_prec: u8, out: &mut String) {
+    match expr {
+        Expr::Binding { var, .. } => out.push_str(&var.name),
+        Expr::Const { val, .. } => write_const(val, out),
+        Expr::Apply { op, args, .. } => {
+            // flattened chains of associative operators print as chains again, and applications
+            // the parser would build differently from their infix form print as function calls
+            let chained = (args.len() == 2 || (args.len() > 2 && op.is_associative()))
+                && infix_round_trips(op, args);
+            if let (Some((sym, prec, right_assoc)), true) = (infix_precedence(op), chained) {
+                let paren = prec < min_prec;
+                if paren {
+                    out.push('(');
+                }
+                let (l_prec, r_prec) = if right_assoc {
+                    (prec + 1, prec)
+                } else if is_comparison(op) {
+                    // comparisons do not chain, so comparisons as operands need parentheses
+                    (COMPARISON_OPERAND_PRECEDENCE, COMPARISON_OPERAND_PRECEDENCE)
+                } else {
+                    (prec, prec + 1)
+                };
+                write_expr(&args[0], l_prec, out);
+                for rhs in &args[1..] {
+                    out.push(' ');
+                    out.push_str(sym);
+                    out.push(' ');
+                    write_expr(rhs, r_prec, out);
+                }
+                if paren {
+                    out.push(')');
+                }
+                return;
+            }
+            match (op.name, &**args) {
+                ("OP_MINUS", [arg]) | ("OP_NEGATE", [arg]) => {
+                    let paren = PREFIX_PRECEDENCE < min_prec;
+                    if paren {
+                        out.push('(');
+                    }
+                    out.push(if **op == OP_MINUS { '-' } else { '!' });
+                    write_expr(arg, PREFIX_PRECEDENCE, out);
+                    if paren {
+                        out.push(')');
+                    }
+                }
+                ("OP_LIST", args) => {
+                    out.push('[');
+                    write_args(args, out);
+                    out.push(']');
+                }
+                ("OP_MAP", args) if args.len() % 2 == 0 => {
+                    out.push('{');
+                    for (i, (k, v)) in args.iter().tuples().enumerate() {
+                        if i > 0 {
+                            out.push_str(", ");
+                        }
+                        write_expr(k, 0, out);
+                        out.push_str(": ");
+                        write_expr(v, 0, out);
+                    }
+                    out.push('}');
+                }
+                // regex arguments are wrapped again when the application is parsed
+                ("OP_REGEX", [arg]) => write_expr(arg, min_prec, out),
+                (name, args) => {
+                    out.push_str(&name.strip_prefix("OP_").unwrap().to_lowercase());
+                    out.push('(');
+                    write_args(args, out);
+                    out.push(')');
+                }
+            }
+        }
+        Expr::Cond { clauses, .. } => {
+            out.push_str("cond(");
+            for (i, (cond, val)) in clauses.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_expr(cond, 0, out);
+                out.push_str(", ");
+                write_expr(val, 0, out);
+            }
+            out.push(')');
+        }
+    }
+}
+
+/// Whether parsing `args` joined by the infix form of `op` gives back the same application:
+/// the parser merges nested chains and drops identity elements
+fn infix_round_trips(op: &'static ExprOp, args: &[Expr]) -> bool {
+    let merged = args.iter().enumerate().any(|(i, arg)| {
+        matches!(arg, Expr::Apply { op: arg_op, .. } if *arg_op == op && merges_into_chain(op, i))
+    });
+    !merged
+        && matches!(
+            simplify_identities(op, args.to_vec(), Default::default()),
+            Expr::Apply { args: rebuilt, .. } if rebuilt.len() == args.len()
+        )
+}
+
+fn write_args(args: &[Expr], out: &mut String) {
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        write_expr(arg, 0, out);
+    }
+}
+
+fn write_const(val: &DataValue, out: &mut String) {
+    match val {
+        DataValue::Num(Num::Float(f)) if f.is_finite() => {
+            // the debug format always keeps a decimal point or an exponent
+            out.push_str(&format!("{f:?}"))
+        }
+        // without literals of their own, so that `nan` and `inf` remain names of bindings
+        DataValue::Num(Num::Float(f)) if f.is_nan() => out.push_str("to_float('NAN')"),
+        DataValue::Num(Num::Float(f)) if *f > 0. => out.push_str("to_float('INF')"),
+        DataValue::Num(Num::Float(_)) => out.push_str("to_float('NEG_INF')"),
+        DataValue::Str(s) => write_quoted(s, out),
+        DataValue::Regex(r) => write_quoted(r.0.as_str(), out),
+        DataValue::Bytes(b) => {
+            out.push_str("hex\"");
+            for byte in b {
+                out.push_str(&format!("{byte:02x}"));
+            }
+            out.push('"');
+        }
+        DataValue::List(l) => {
+            out.push('[');
+            for (i, v) in l.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_const(v, out);
+            }
+            out.push(']');
+        }
+        DataValue::Map(m) => {
+            out.push('{');
+            for (i, (k, v)) in m.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_const(k, out);
+                out.push_str(": ");
+                write_const(v, out);
+            }
+            out.push('}');
+        }
+        v => out.push_str(&v.to_string()),
+    }
+}
+
+// single-quoted strings always process escapes, unlike raw strings
+fn write_quoted(s: &str, out: &mut String) {
+    out.push('\'');
+    for c in s.chars() {
+        match c {
+            '\'' => out.push_str("\\'"),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\x08' => out.push_str("\\b"),
+            '\x0c' => out.push_str("\\f"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+}
+
+/// A parse error in a form that tools such as editors can use without rendering it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDiagnostic {
+    /// The diagnostic code, e.g. `parser::bad_float`
+    pub code: String,
+    /// Byte offset and length of the offending input
+    pub span: (usize, usize),
+    /// The error message, without the source it refers to
+    pub message: String,
+}
+
+impl From<&miette::Report> for ParseDiagnostic {
+    fn from(err: &miette::Report) -> Self {
+        Self {
+            code: err.code().map(|c| c.to_string()).unwrap_or_default(),
+            span: err
+                .labels()
+                .and_then(|mut labels| labels.next())
+                .map(|label| (label.offset(), label.len()))
+                .unwrap_or_default(),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Parses a standalone expression, which must not contain parameters,
+/// reporting failures as structured diagnostics. Errors in independent
+/// subexpressions are all reported, in source order.
+pub fn parse_expr_structured(src: &str) -> std::result::Result<Expr, Vec<ParseDiagnostic>> {
+    let parse = || -> Result<Expr> {
+        check_bracket_depth(src)?;
+        let parsed = CozoScriptParser::parse(Rule::expr_with_term, src)
+            .map_err(ParseError::from)?
+            .next()
+            .unwrap();
+        build_expr(parsed.into_inner().next().unwrap(), &Default::default())
+    };
+    parse().map_err(|err| match err.downcast::<ExprErrors>() {
+        Ok(ExprErrors(errs)) => errs.iter().map(ParseDiagnostic::from).collect(),
+        Err(err) => vec![ParseDiagnostic::from(&err)],
+    })
+}
+
+#[derive(Debug, Error)]
+#[error("Found {} errors in expression", .0.len())]
+pub(crate) struct ExprErrors(pub(crate) Vec<miette::Report>);
+
+impl Diagnostic for ExprErrors {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        Some(Box::new("parser::multiple_errors"))
+    }
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        Some(Box::new(
+            self.0.iter().map(|err| err.as_ref() as &dyn Diagnostic),
+        ))
+    }
+}
+
+/// Combines the errors of independently built subexpressions, so that all of them are reported
+fn merge_errors(errs: impl IntoIterator<Item = miette::Report>) -> miette::Report {
+    let mut all = vec![];
+    for err in errs {
+        match err.downcast::<ExprErrors>() {
+            Ok(ExprErrors(inner)) => all.extend(inner),
+            Err(err) => all.push(err),
+        }
+    }
+    if all.len() == 1 {
+        all.pop().unwrap()
+    } else {
+        ExprErrors(all).into()
+    }
+}
+
+/// Like `try_collect`, but keeps going after the first error to collect all of them
+fn collect_all<T>(results: impl IntoIterator<Item = Result<T>>) -> Result<Vec<T>> {
+    let mut oks = vec![];
+    let mut errs = vec![];
+    for result in results {
+        match result {
+            Ok(v) => oks.push(v),
+            Err(err) => errs.push(err),
+        }
+    }
+    if errs.is_empty() {
+        Ok(oks)
+    } else {
+        Err(merge_errors(errs))
+    }
+}
+
+/// How deeply subexpressions may nest, so that hostile input cannot exhaust the stack when the
+/// expression is built, compiled or dropped, all of which recurse into subexpressions
+pub(crate) const MAX_EXPR_DEPTH: usize = 128;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Expression is nested too deeply")]
+#[diagnostic(code(parser::nesting_too_deep))]
+pub(crate) struct NestingTooDeepError(#[label] pub(crate) SourceSpan);
+
+/// How deeply brackets may nest in source text. The grammar recurses into every level, so this
+/// is checked before parsing. Brackets in strings and comments count as well, which can only make
+/// the check stricter.
+const MAX_BRACKET_DEPTH: usize = 2 * MAX_EXPR_DEPTH;
+
+pub(crate) fn check_bracket_depth(src: &str) -> Result<()> {
+    let mut depth = 0usize;
+    for (i, b) in src.bytes().enumerate() {
+        match b {
+            b'(' | b'[' | b'{' => {
+                depth += 1;
+                ensure!(
+                    depth <= MAX_BRACKET_DEPTH,
+                    NestingTooDeepError(SourceSpan(i, 1))
+                );
+            }
+            b')' | b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
 pub(crate) fn build_expr(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result<Expr> {
+    build_nested_expr(pair, param_pool, MAX_EXPR_DEPTH)
+}
+
+/// `depth_left` is how many more levels of subexpressions may be entered
+pub(crate) fn build_nested_expr(
+    pair: Pair<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    depth_left: usize,
+) -> Result<Expr> {
     ensure!(
         pair.as_rule() == Rule::expr,
         InvalidExpression(pair.extract_span())
     );
+    let depth_left = depth_left
+        .checked_sub(nesting_cost(&pair))
+        .ok_or_else(|| NestingTooDeepError(pair.extract_span()))?;
+    reject_chained_comparisons(&pair)?;
 
-    PRATT_PARSER
-        .map_primary(|v| build_term(v, param_pool))
+    let built = PRATT_PARSER
+        .map_primary(|v| build_term(v, param_pool, depth_left))
         .map_infix(build_expr_infix)
         .map_prefix(|op, rhs| {
             let rhs = rhs?;
@@ -134,11 +652,137 @@ pub(crate) fn build_expr(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue
                 _ => unreachable!(),
             })
         })
-        .parse(pair.into_inner())
+        .map_postfix(|lhs, op| {
+            let op_span = op.extract_span();
+            Ok(match op.as_rule() {
+                Rule::index_access => {
+                    let idx =
+                        build_nested_expr(op.into_inner().next().unwrap(), param_pool, depth_left);
+                    let args = collect_all([lhs, idx])?;
+                    Expr::Apply {
+                        op: &OP_MAYBE_GET,
+                        span: args[0].span().merge(op_span),
+                        args: args.into(),
+                    }
+                }
+                Rule::slice_access => {
+                    let bounds = op
+                        .into_inner()
+                        .map(|bound| {
+                            let kind = bound.as_rule();
+                            build_nested_expr(
+                                bound.into_inner().next().unwrap(),
+                                param_pool,
+                                depth_left,
+                            )
+                            .map(|ex| (kind, ex))
+                        })
+                        .collect_vec();
+                    let (lhs, bounds) = match (lhs, collect_all(bounds)) {
+                        (Ok(lhs), Ok(bounds)) => (lhs, bounds),
+                        (lhs, bounds) => {
+                            return Err(merge_errors(lhs.err().into_iter().chain(bounds.err())))
+                        }
+                    };
+                    let span = lhs.span().merge(op_span);
+                    let mut start = Expr::Const {
+                        val: DataValue::Null,
+                        span,
+                    };
+                    let mut end = start.clone();
+                    for (kind, ex) in bounds {
+                        if kind == Rule::slice_start {
+                            start = ex;
+                        } else {
+                            end = ex;
+                        }
+                    }
+                    Expr::Apply {
+                        op: &OP_SUBLIST,
+                        args: [lhs, start, end].into(),
+                        span,
+                    }
+                }
+                _ => unreachable!(),
+            })
+        })
+        .parse(pair.into_inner());
+    built
+}
+
+/// The levels of nesting that `pair` adds on top of its terms: one for itself and one for every
+/// operator, since precedence climbing recurses into prefix and right-associative operators, and
+/// chains of the other operators become deep trees. Only chains that are flattened into a single
+/// node by `build_expr_infix` are free.
+fn nesting_cost(pair: &Pair<'_>) -> usize {
+    let nesting = pair.clone().into_inner().filter(|p| {
+        matches!(
+            p.as_rule(),
+            Rule::minus
+                | Rule::negate
+                | Rule::index_access
+                | Rule::slice_access
+                | Rule::op_sub
+                | Rule::op_div
+                | Rule::op_mod
+                | Rule::op_pow
+                | Rule::op_coalesce
+                | Rule::op_eq
+                | Rule::op_ne
+                | Rule::op_gt
+                | Rule::op_lt
+                | Rule::op_ge
+                | Rule::op_le
+                | Rule::op_cmp
+                | Rule::op_in
+        )
+    });
+    1 + nesting.count()
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Comparisons cannot be chained")]
+#[diagnostic(code(parser::chained_comparison))]
+#[diagnostic(help("To compare both, write `a {1} b && b {2} c`"))]
+pub(crate) struct ChainedComparisonError(#[label] pub(crate) SourceSpan, String, String);
+
+/// Comparisons are left-associative like other operators, so `a == b == c` would compare the
+/// boolean `a == b` with `c`, which is almost never intended. The operators at the top level of the
+/// expression are checked before precedence climbing, where groupings are single terms, so
+/// `(a == b) == c` is still allowed.
+fn reject_chained_comparisons(pair: &Pair<'_>) -> Result<()> {
+    let mut last_cmp: Option<Pair<'_>> = None;
+    for p in pair.clone().into_inner() {
+        match p.as_rule() {
+            Rule::op_eq
+            | Rule::op_ne
+            | Rule::op_gt
+            | Rule::op_ge
+            | Rule::op_lt
+            | Rule::op_le
+            | Rule::op_cmp => {
+                if let Some(prev) = last_cmp {
+                    bail!(ChainedComparisonError(
+                        prev.extract_span().merge(p.extract_span()),
+                        prev.as_str().to_string(),
+                        p.as_str().to_string(),
+                    ));
+                }
+                last_cmp = Some(p);
+            }
+            Rule::op_and | Rule::op_or => last_cmp = None,
+            _ => {}
+        }
+    }
+    Ok(())
 }
 
 fn build_expr_infix(lhs: Result<Expr>, op: Pair<'_>, rhs: Result<Expr>) -> Result<Expr> {
-    let args = vec![lhs?, rhs?];
+    let args = collect_all([lhs, rhs])?;
+    let span = args[0]
+        .span()
+        .merge(op.extract_span())
+        .merge(args[1].span());
     let op = match op.as_rule() {
         Rule::op_add => &OP_ADD,
         Rule::op_sub => &OP_SUB,
@@ -152,23 +796,88 @@ fn build_expr_infix(lhs: Result<Expr>, op: Pair<'_>, rhs: Result<Expr>) -> Resul
         Rule::op_ge => &OP_GE,
         Rule::op_lt => &OP_LT,
         Rule::op_le => &OP_LE,
+        Rule::op_cmp => &OP_CMP,
+        Rule::op_in => &OP_IN,
         Rule::op_concat => &OP_CONCAT,
         Rule::op_or => &OP_OR,
         Rule::op_and => &OP_AND,
         Rule::op_coalesce => &OP_COALESCE,
         _ => unreachable!(),
     };
-    let start = args[0].span().0;
-    let end = args[1].span().0 + args[1].span().1;
-    let length = end - start;
-    Ok(Expr::Apply {
+    // chains of an associative operator become a single n-ary node, so that `and` and `or` stop at
+    // the first decisive value. A parenthesized right operand of `+` or `*` is kept apart, as
+    // merging it would change how floats are rounded.
+    let args = args
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, arg)| match arg {
+            Expr::Apply {
+                op: arg_op,
+                args: arg_args,
+                ..
+            } if *arg_op == *op && merges_into_chain(op, i) => arg_args.into_vec(),
+            arg => vec![arg],
+        })
+        .collect_vec();
+    Ok(simplify_identities(op, args, span))
+}
+
+/// Whether an application of `op` as the operand at `pos` of `op` is merged into the chain
+fn merges_into_chain(op: &ExprOp, pos: usize) -> bool {
+    op.vararg && op.is_associative() && (pos == 0 || (*op != OP_ADD && *op != OP_MUL))
+}
+
+/// Drops operands that cannot change the result, as in `x + 0` or `x && true`, and folds `x * 0`.
+/// This is only done when the types are known statically, so that errors and the int/float
+/// distinction are kept: `s + 0` for a string `s` still fails. `x * 0` is only folded if `x` is an
+/// int without side effects.
+fn simplify_identities(op: &'static ExprOp, args: Vec<Expr>, span: SourceSpan) -> Expr {
+    let is_int_zero = |e: &Expr| matches!(e, Expr::Const { val, .. } if *val == DataValue::from(0));
+    if *op == OP_MUL && args.iter().any(is_int_zero) {
+        let foldable = args
+            .iter()
+            .all(|e| e.infer_type() == Some(ValueType::Int) && !e.has_side_effect());
+        if foldable {
+            return Expr::Const {
+                val: DataValue::from(0),
+                span,
+            };
+        }
+    }
+    // a left identity can be dropped anywhere only if the operands can be reordered
+    if let Some(identity) = op.identity_element().filter(|_| op.is_commutative()) {
+        let identity_type = ValueType::of(&identity);
+        let (ids, mut rest): (Vec<_>, Vec<_>) = args
+            .iter()
+            .cloned()
+            .partition(|e| matches!(e, Expr::Const { val, .. } if *val == identity));
+        let removable = !ids.is_empty()
+            && !rest.is_empty()
+            && rest.iter().all(|e| e.infer_type() == identity_type);
+        if removable {
+            return if rest.len() == 1 {
+                rest.pop().unwrap()
+            } else {
+                Expr::Apply {
+                    op,
+                    args: rest.into(),
+                    span,
+                }
+            };
+        }
+    }
+    Expr::Apply {
         op,
         args: args.into(),
-        span: SourceSpan(start, length),
-    })
+        span,
+    }
 }
 
-fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result<Expr> {
+fn build_term(
+    pair: Pair<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    depth_left: usize,
+) -> Result<Expr> {
     let span = pair.extract_span();
     let op = pair.as_rule();
     Ok(match op {
@@ -192,37 +901,30 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
             }
         }
         Rule::pos_int => {
-            #[derive(Error, Diagnostic, Debug)]
-            #[error("Cannot parse integer")]
-            #[diagnostic(code(parser::bad_pos_int))]
-            struct BadIntError(#[label] SourceSpan);
-
-            let i = pair
-                .as_str()
-                .replace('_', "")
-                .parse::<i64>()
-                .map_err(|_| BadIntError(span))?;
+            let i = strip_digit_separators(pair.as_str())
+                .and_then(|digits| digits.parse::<i64>().ok())
+                .ok_or(BadIntError(span))?;
             Expr::Const {
                 val: DataValue::from(i),
                 span,
             }
         }
         Rule::hex_pos_int => {
-            let i = parse_int(pair.as_str(), 16);
+            let i = parse_int(pair.as_str(), 16).map_err(|_| BadIntError(span))?;
             Expr::Const {
                 val: DataValue::from(i),
                 span,
             }
         }
         Rule::octo_pos_int => {
-            let i = parse_int(pair.as_str(), 8);
+            let i = parse_int(pair.as_str(), 8).map_err(|_| BadIntError(span))?;
             Expr::Const {
                 val: DataValue::from(i),
                 span,
             }
         }
         Rule::bin_pos_int => {
-            let i = parse_int(pair.as_str(), 2);
+            let i = parse_int(pair.as_str(), 2).map_err(|_| BadIntError(span))?;
             Expr::Const {
                 val: DataValue::from(i),
                 span,
@@ -244,6 +946,14 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
                 span,
             }
         }
+        Rule::percent => {
+            let base = build_term(pair.into_inner().next().unwrap(), param_pool, depth_left)?;
+            let f = base.get_const().and_then(|v| v.get_float()).unwrap();
+            Expr::Const {
+                val: DataValue::from(f / 100.),
+                span,
+            }
+        }
         Rule::null => Expr::Const {
             val: DataValue::Null,
             span,
@@ -259,32 +969,115 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
                 span,
             }
         }
+        Rule::hex_bytes | Rule::b64_bytes => {
+            #[derive(Error, Diagnostic, Debug)]
+            #[error("Cannot decode bytes literal")]
+            #[diagnostic(code(parser::bad_bytes))]
+            struct BadBytesError(#[label] SourceSpan);
+
+            let encoded = DataValue::from(pair.into_inner().next().unwrap().as_str());
+            let val = if op == Rule::hex_bytes {
+                op_from_hex(&[encoded])
+            } else {
+                op_decode_base64(&[encoded])
+            }
+            .map_err(|_| BadBytesError(span))?;
+            Expr::Const { val, span }
+        }
         Rule::list => {
+            // with spreads, `[a, ..b, c]` becomes `concat([a], b, [c])`, leaving out empty lists
+            // where possible
+            let items = collect_all(pair.into_inner().map(|p| {
+                if p.as_rule() == Rule::spread {
+                    build_nested_expr(p.into_inner().next().unwrap(), param_pool, depth_left)
+                        .map(|ex| (true, ex))
+                } else {
+                    build_nested_expr(p, param_pool, depth_left).map(|ex| (false, ex))
+                }
+            }))?;
+            let mut segments = vec![];
             let mut collected = vec![];
-            for p in pair.into_inner() {
-                collected.push(build_expr(p, param_pool)?)
+            for (is_spread, ex) in items {
+                if is_spread {
+                    if !collected.is_empty() {
+                        segments.push(Expr::Apply {
+                            op: &OP_LIST,
+                            args: mem::take(&mut collected).into(),
+                            span,
+                        });
+                    }
+                    segments.push(ex);
+                } else {
+                    collected.push(ex)
+                }
             }
-            Expr::Apply {
-                op: &OP_LIST,
-                args: collected.into(),
-                span,
+            if segments.is_empty() {
+                Expr::Apply {
+                    op: &OP_LIST,
+                    args: collected.into(),
+                    span,
+                }
+            } else {
+                if !collected.is_empty() {
+                    segments.push(Expr::Apply {
+                        op: &OP_LIST,
+                        args: collected.into(),
+                        span,
+                    });
+                }
+                // with a list among the arguments, `concat` rejects spreading strings
+                if !segments
+                    .iter()
+                    .any(|ex| matches!(ex, Expr::Apply { op, .. } if **op == OP_LIST))
+                {
+                    segments.insert(
+                        0,
+                        Expr::Apply {
+                            op: &OP_LIST,
+                            args: [].into(),
+                            span,
+                        },
+                    );
+                }
+                Expr::Apply {
+                    op: &OP_CONCAT,
+                    args: segments.into(),
+                    span,
+                }
             }
         }
+        // keys and values alternate in the arguments of `map`
+        Rule::map => Expr::Apply {
+            op: &OP_MAP,
+            args: collect_all(
+                pair.into_inner()
+                    .map(|p| build_nested_expr(p, param_pool, depth_left)),
+            )?
+            .into(),
+            span,
+        },
         Rule::apply => {
             let mut p = pair.into_inner();
             let ident_p = p.next().unwrap();
             let ident = ident_p.as_str();
-            let mut args: Vec<_> = p
-                .next()
-                .unwrap()
-                .into_inner()
-                .map(|v| build_expr(v, param_pool))
-                .try_collect()?;
+            let args = collect_all(
+                p.next()
+                    .unwrap()
+                    .into_inner()
+                    .map(|v| build_nested_expr(v, param_pool, depth_left)),
+            );
             #[derive(Error, Diagnostic, Debug)]
             #[error("Named function '{0}' not found")]
             #[diagnostic(code(parser::func_not_function))]
             struct FuncNotFoundError(String, #[label] SourceSpan);
 
+            // an unknown function is reported along with any errors in its arguments
+            if !matches!(ident, "cond" | "if") && get_op(ident).is_none() {
+                let err = FuncNotFoundError(ident.to_string(), ident_p.extract_span()).into();
+                return Err(merge_errors(iter::once(err).chain(args.err())));
+            }
+            let mut args = args?;
+
             match ident {
                 "cond" => {
                     if args.is_empty() {
@@ -355,9 +1148,7 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
                     Expr::Cond { clauses, span }
                 }
                 _ => {
-                    let op = get_op(ident).ok_or_else(|| {
-                        FuncNotFoundError(ident.to_string(), ident_p.extract_span())
-                    })?;
+                    let op = get_op(ident).unwrap();
                     op.post_process_args(&mut args);
 
                     #[derive(Error, Diagnostic, Debug)]
@@ -392,19 +1183,35 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
                 }
             }
         }
-        Rule::grouping => build_expr(pair.into_inner().next().unwrap(), param_pool)?,
+        Rule::grouping => {
+            build_nested_expr(pair.into_inner().next().unwrap(), param_pool, depth_left)?
+        }
         r => unreachable!("Encountered unknown op {:?}", r),
     })
 }
 
-pub(crate) fn parse_int(s: &str, radix: u32) -> i64 {
-    i64::from_str_radix(&s[2..].replace('_', ""), radix).unwrap()
+#[derive(Error, Diagnostic, Debug)]
+#[error("Cannot parse integer")]
+#[diagnostic(code(parser::bad_pos_int))]
+struct BadIntError(#[label] SourceSpan);
+
+/// Removes the `_` separators of the digits of an integer, which may only appear between two digits
+fn strip_digit_separators(digits: &str) -> Option<String> {
+    let misplaced = digits.starts_with('_') || digits.ends_with('_') || digits.contains("__");
+    (!misplaced).then(|| digits.replace('_', ""))
+}
+
+/// Parses an integer after a two-character prefix such as `0x` or `\u`
+pub(crate) fn parse_int(s: &str, radix: u32) -> Result<i64> {
+    let digits = strip_digit_separators(&s[2..])
+        .ok_or_else(|| miette!("misplaced digit separator in {}", s))?;
+    i64::from_str_radix(&digits, radix).into_diagnostic()
 }
 
 pub(crate) fn parse_string(pair: Pair<'_>) -> Result<SmartString<LazyCompact>> {
     match pair.as_rule() {
-        Rule::quoted_string => Ok(parse_quoted_string(pair)?),
-        Rule::s_quoted_string => Ok(parse_s_quoted_string(pair)?),
+        Rule::quoted_string => Ok(parse_escaped_string(pair, '"')?),
+        Rule::s_quoted_string => Ok(parse_escaped_string(pair, '\'')?),
         Rule::raw_string => Ok(parse_raw_string(pair)?),
         Rule::ident => Ok(SmartString::from(pair.as_str())),
         t => unreachable!("{:?}", t),
@@ -416,47 +1223,41 @@ pub(crate) fn parse_string(pair: Pair<'_>) -> Result<SmartString<LazyCompact>> {
 #[diagnostic(code(parser::invalid_utf8_code))]
 struct InvalidUtf8Error(u32, #[label] SourceSpan);
 
+#[derive(Error, Diagnostic, Debug)]
+#[error("unpaired UTF-16 surrogate {0:#X}")]
+#[diagnostic(code(parser::unpaired_surrogate))]
+#[diagnostic(help("Surrogates must come in pairs, a high one followed by a low one"))]
+struct UnpairedSurrogateError(u32, #[label] SourceSpan);
+
 #[derive(Error, Diagnostic, Debug)]
 #[error("invalid escape sequence {0}")]
 #[diagnostic(code(parser::invalid_escape_seq))]
 struct InvalidEscapeSeqError(String, #[label] SourceSpan);
 
-fn parse_quoted_string(pair: Pair<'_>) -> Result<SmartString<LazyCompact>> {
+/// Shared by single- and double-quoted strings, which differ only in the quote that is escaped
+fn parse_escaped_string(pair: Pair<'_>, quote: char) -> Result<SmartString<LazyCompact>> {
     let pairs = pair.into_inner().next().unwrap().into_inner();
     let mut ret = SmartString::new();
+    // JSON-style UTF-16 surrogate pairs, e.g. `\uD83D\uDE00`
+    let mut high_surrogate: Option<(u32, SourceSpan)> = None;
     for pair in pairs {
         let s = pair.as_str();
-        match s {
-            r#"\""# => ret.push('"'),
-            r"\\" => ret.push('\\'),
-            r"\/" => ret.push('/'),
-            r"\b" => ret.push('\x08'),
-            r"\f" => ret.push('\x0c'),
-            r"\n" => ret.push('\n'),
-            r"\r" => ret.push('\r'),
-            r"\t" => ret.push('\t'),
-            s if s.starts_with(r"\u") => {
-                let code = parse_int(s, 16) as u32;
-                let ch = char::from_u32(code)
-                    .ok_or_else(|| InvalidUtf8Error(code, pair.extract_span()))?;
-                ret.push(ch);
-            }
-            s if s.starts_with('\\') => {
-                bail!(InvalidEscapeSeqError(s.to_string(), pair.extract_span()))
-            }
-            s => ret.push_str(s),
+        if let Some((high, span)) = high_surrogate.take() {
+            let low = if s.starts_with(r"\u") && s.len() == 6 {
+                parse_int(s, 16)? as u32
+            } else {
+                0
+            };
+            ensure!(
+                (0xDC00..0xE000).contains(&low),
+                UnpairedSurrogateError(high, span)
+            );
+            let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+            ret.push(char::from_u32(code).unwrap());
+            continue;
         }
-    }
-    Ok(ret)
-}
-
-fn parse_s_quoted_string(pair: Pair<'_>) -> Result<SmartString<LazyCompact>> {
-    let pairs = pair.into_inner().next().unwrap().into_inner();
-    let mut ret = SmartString::new();
-    for pair in pairs {
-        let s = pair.as_str();
         match s {
-            r#"\'"# => ret.push('\''),
+            s if s.len() == 2 && s.starts_with('\\') && s.ends_with(quote) => ret.push(quote),
             r"\\" => ret.push('\\'),
             r"\/" => ret.push('/'),
             r"\b" => ret.push('\x08'),
@@ -464,8 +1265,25 @@ fn parse_s_quoted_string(pair: Pair<'_>) -> Result<SmartString<LazyCompact>> {
             r"\n" => ret.push('\n'),
             r"\r" => ret.push('\r'),
             r"\t" => ret.push('\t'),
-            s if s.starts_with(r"\u") => {
-                let code = parse_int(s, 16) as u32;
+            r"\0" => ret.push('\0'),
+            s if s.starts_with(r"\x") => {
+                let digits = &s[2..];
+                ensure!(
+                    digits.len() == 2 && digits.bytes().all(|b| b.is_ascii_hexdigit()),
+                    InvalidEscapeSeqError(s.to_string(), pair.extract_span())
+                );
+                ret.push(char::from(u8::from_str_radix(digits, 16).unwrap()));
+            }
+            s if s.starts_with(r"\u") && s.len() == 6 => {
+                let code = parse_int(s, 16)? as u32;
+                if (0xD800..0xDC00).contains(&code) {
+                    high_surrogate = Some((code, pair.extract_span()));
+                    continue;
+                }
+                ensure!(
+                    !(0xDC00..0xE000).contains(&code),
+                    UnpairedSurrogateError(code, pair.extract_span())
+                );
                 let ch = char::from_u32(code)
                     .ok_or_else(|| InvalidUtf8Error(code, pair.extract_span()))?;
                 ret.push(ch);
@@ -476,6 +1294,9 @@ fn parse_s_quoted_string(pair: Pair<'_>) -> Result<SmartString<LazyCompact>> {
             s => ret.push_str(s),
         }
     }
+    if let Some((high, span)) = high_surrogate {
+        bail!(UnpairedSurrogateError(high, span))
+    }
     Ok(ret)
 }
 
@@ -21,6 +21,7 @@ use thiserror::Error;
 use crate::data::program::InputProgram;
 use crate::data::relation::NullableColType;
 use crate::data::value::{DataValue, ValidityTs};
+use crate::parse::expr::check_bracket_depth;
 use crate::parse::imperative::parse_imperative_block;
 use crate::parse::query::parse_query;
 use crate::parse::schema::parse_nullable_type;
@@ -173,6 +174,48 @@ impl SourceSpan {
         let e = max(e1, e2);
         Self(s, e - s)
     }
+    /// Locates the start and the end of the span in `src`, the source it was produced from,
+    /// for tools such as editors that do not count in bytes.
+    /// Offsets past the end of `src` are clamped to it.
+    pub fn locate(&self, src: &str) -> (SourcePosition, SourcePosition) {
+        let start = SourcePosition::at(src, self.0);
+        let end = SourcePosition::at(src, self.0 + self.1);
+        (start, end)
+    }
+}
+
+/// A position in the source script. Lines and columns are zero-based.
+#[derive(Eq, PartialEq, Debug, Copy, Clone, Default)]
+pub struct SourcePosition {
+    /// Line number
+    pub line: usize,
+    /// Column, counted in Unicode scalar values
+    pub column: usize,
+    /// Column, counted in UTF-16 code units
+    pub utf16_column: usize,
+    /// Offset from the start of the source, counted in UTF-16 code units
+    pub utf16_offset: usize,
+}
+
+impl SourcePosition {
+    fn at(src: &str, byte_offset: usize) -> Self {
+        let mut pos = Self::default();
+        for (idx, c) in src.char_indices() {
+            if idx >= byte_offset {
+                break;
+            }
+            if c == '\n' {
+                pos.line += 1;
+                pos.column = 0;
+                pos.utf16_column = 0;
+            } else {
+                pos.column += 1;
+                pos.utf16_column += c.len_utf16();
+            }
+            pos.utf16_offset += c.len_utf16();
+        }
+        pos
+    }
 }
 
 impl From<&'_ SourceSpan> for miette::SourceSpan {
@@ -195,7 +238,18 @@ pub(crate) struct ParseError {
     pub(crate) span: SourceSpan,
 }
 
+impl From<pest::error::Error<Rule>> for ParseError {
+    fn from(err: pest::error::Error<Rule>) -> Self {
+        let span = match err.location {
+            InputLocation::Pos(p) => SourceSpan(p, 0),
+            InputLocation::Span((start, end)) => SourceSpan(start, end - start),
+        };
+        ParseError { span }
+    }
+}
+
 pub(crate) fn parse_type(src: &str) -> Result<NullableColType> {
+    check_bracket_depth(src)?;
     let parsed = CozoScriptParser::parse(Rule::col_type_with_term, src)
         .into_diagnostic()?
         .next()
@@ -209,14 +263,9 @@ pub(crate) fn parse_script(
     fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
 ) -> Result<CozoScript> {
+    check_bracket_depth(src)?;
     let parsed = CozoScriptParser::parse(Rule::script, src)
-        .map_err(|err| {
-            let span = match err.location {
-                InputLocation::Pos(p) => SourceSpan(p, 0),
-                InputLocation::Span((start, end)) => SourceSpan(start, end - start),
-            };
-            ParseError { span }
-        })?
+        .map_err(ParseError::from)?
         .next()
         .unwrap();
     Ok(match parsed.as_rule() {
@@ -63,7 +63,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 } else {
                     op_to_bool(&row[row.len() - 1..])?
                         .get_bool()
-                        .ok_or_else(|| PredicateTypeError(span, row.last().cloned().unwrap()))?
+                        .ok_or_else(|| PredicateTypeError::new(span, row.last().unwrap()))?
                 }
             }
         })
@@ -235,6 +235,45 @@ fn strict_checks_for_fixed_rules_args() {
     assert!(res.is_err());
 }
 
+#[test]
+fn underscores_in_one_application_are_distinct() {
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            r#"
+        r[] <- [[1, 'a', 1], [2, 'b', 3]]
+
+        ?[x] := r[_, _, x]
+        "#,
+            Default::default(),
+        )
+        .unwrap()
+        .rows;
+    assert_eq!(
+        res,
+        vec![vec![DataValue::from(1)], vec![DataValue::from(3)]]
+    );
+
+    db.run_script(
+        r#"
+        ?[a, b, c] <- [[1, 'a', 1], [2, 'b', 3]]
+        :create u {a, b, c}
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("?[x] := *u[_, _, x]", Default::default())
+        .unwrap()
+        .rows;
+    assert_eq!(res.len(), 2);
+
+    // `_` never binds, so it cannot be used for its value
+    assert!(db
+        .run_script("?[x] := r[_, _, x], x > _", Default::default())
+        .is_err());
+}
+
 #[test]
 fn do_not_unify_underscore() {
     let db = new_cozo_mem().unwrap();